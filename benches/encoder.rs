@@ -6,7 +6,7 @@ use tokio_util::codec::Encoder;
 
 fn generate_frame() -> AMQPFrame {
     let args = frame::QueueDeclareArgs {
-        name: "test queue".into(),
+        queue: "test queue".into(),
         ..Default::default()
     };
 
@@ -14,13 +14,13 @@ fn generate_frame() -> AMQPFrame {
 }
 
 fn method_frame(bench: &mut Bencher) {
-    let mut codec = ironmq_codec::codec::AMQPCodec {};
+    let mut codec = ironmq_codec::codec::AMQPCodec::default();
     let frame = generate_frame();
 
     bench.iter(move || {
         let mut buf = BytesMut::with_capacity(1024);
 
-        codec.encode(&frame, &mut buf)
+        codec.encode(frame.clone(), &mut buf)
     });
 }
 
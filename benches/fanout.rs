@@ -0,0 +1,37 @@
+use bencher::{black_box, Bencher};
+use bytes::Bytes;
+use ironmq_codec::frame;
+
+/// A message of 1 MB sent to an exchange which is bound to 50 queues.
+const PAYLOAD_SIZE: usize = 1024 * 1024;
+const QUEUES: usize = 50;
+
+/// Every queue gets a body frame with its own copy of the payload.
+fn fanout_copy(bench: &mut Bencher) {
+    let payload = vec![0x55u8; PAYLOAD_SIZE];
+
+    bench.iter(|| {
+        let frames = (0..QUEUES)
+            .map(|_| frame::content_body(1, payload.as_slice()))
+            .collect::<Vec<_>>();
+
+        black_box(frames)
+    });
+}
+
+/// Every queue gets a body frame which shares the payload buffer.
+fn fanout_shared(bench: &mut Bencher) {
+    let payload = Bytes::from(vec![0x55u8; PAYLOAD_SIZE]);
+
+    bench.iter(|| {
+        let frames = (0..QUEUES)
+            .map(|_| frame::content_body_bytes(1, payload.clone()))
+            .collect::<Vec<_>>();
+
+        black_box(frames)
+    });
+}
+
+bencher::benchmark_group!(fanout, fanout_copy, fanout_shared);
+
+bencher::benchmark_main!(fanout);
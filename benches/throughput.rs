@@ -0,0 +1,73 @@
+use bencher::Bencher;
+use bytes::BytesMut;
+use ironmq_codec::codec::AMQPCodec;
+use ironmq_codec::frame::{self, AMQPFrame};
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use tokio_util::codec::Encoder;
+
+/// Number of messages published in one iteration.
+const MESSAGES: usize = 100;
+
+/// Connect to a local socket whose reader discards everything, so the cost of the writes can be
+/// measured.
+fn sink_socket() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let (mut reader, _) = listener.accept().unwrap();
+        let _ = io::copy(&mut reader, &mut io::sink());
+    });
+
+    let socket = TcpStream::connect(addr).unwrap();
+    socket.set_nodelay(true).unwrap();
+    socket
+}
+
+fn publish_frames() -> Vec<AMQPFrame> {
+    vec![
+        frame::basic_publish(1, "exchange", "routing-key"),
+        AMQPFrame::ContentHeader(frame::content_header(1, 100)),
+        AMQPFrame::ContentBody(frame::content_body(1, &[0x55u8; 100])),
+    ]
+}
+
+/// Every frame is encoded and written to the socket separately.
+fn publish_frame_by_frame(bench: &mut Bencher) {
+    let mut socket = sink_socket();
+    let mut codec = AMQPCodec::default();
+    let frames = publish_frames();
+    let mut buf = BytesMut::with_capacity(4096);
+
+    bench.iter(|| {
+        for _ in 0..MESSAGES {
+            for f in &frames {
+                codec.encode(f.clone(), &mut buf).unwrap();
+                socket.write_all(&buf).unwrap();
+                buf.clear();
+            }
+        }
+    });
+}
+
+/// The frames of a message are encoded into one buffer and written at once.
+fn publish_batch(bench: &mut Bencher) {
+    let mut socket = sink_socket();
+    let mut codec = AMQPCodec::default();
+    let frames = publish_frames();
+    let mut buf = BytesMut::with_capacity(4096);
+
+    bench.iter(|| {
+        for _ in 0..MESSAGES {
+            codec.encode(frames.clone(), &mut buf).unwrap();
+            socket.write_all(&buf).unwrap();
+            buf.clear();
+        }
+    });
+}
+
+bencher::benchmark_group!(throughput, publish_frame_by_frame, publish_batch);
+
+bencher::benchmark_main!(throughput);
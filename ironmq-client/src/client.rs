@@ -1,20 +1,27 @@
 use crate::client_sm::{self, ClientState};
-use crate::{client_error, Client, MessageSink, Result};
+use crate::{client_error, Client, Error, MessageSink, ReplyCode, Result};
+use bytes::Bytes;
+use futures::future;
 use futures::stream::StreamExt;
 use futures::SinkExt;
-use ironmq_codec::codec::AMQPCodec;
+use ironmq_codec::codec::{AMQPCodec, DEFAULT_FRAME_MAX};
+use ironmq_codec::content::content_body_frames;
 use ironmq_codec::frame::{self, AMQPFrame, MethodFrameArgs};
-use log::{debug, error};
+use ironmq_codec::protocol::{Event, ProtocolError};
+use ironmq_codec::{FrameError, ProtocolHeaderError};
+use log::{debug, error, warn};
 use std::collections::HashMap;
 use std::fmt;
+use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::sync::{mpsc, oneshot};
+use tokio::time;
 use tokio_util::codec::Framed;
 
 pub(crate) enum Param {
     Frame(AMQPFrame),
     Consume(AMQPFrame, MessageSink),
-    Publish(AMQPFrame, Vec<u8>)
+    Publish(AMQPFrame, Bytes)
 }
 
 /// Response for passing errors to the client API.
@@ -59,50 +66,92 @@ pub(crate) async fn create_connection(url: String) -> Result<Client> {
 }
 
 async fn socket_loop(socket: TcpStream, mut receiver: mpsc::Receiver<Request>) -> Result<()> {
-    let (mut sink, mut stream) = Framed::new(socket, AMQPCodec {}).split();
+    let mut framed = Framed::new(socket, AMQPCodec::new(DEFAULT_FRAME_MAX));
     let mut client = client_sm::new();
     let mut feedback: HashMap<u16, Response> = HashMap::new();
+    // started after tune-ok, so the server sees that we are alive
+    let mut heartbeat: Option<time::Interval> = None;
 
     loop {
         tokio::select! {
-            result = stream.next() => {
+            result = framed.next() => {
                 match result {
+                    Some(Ok(AMQPFrame::Header)) => {
+                        // Server sends its protocol header only if it doesn't accept ours.
+                        return reject_protocol(&mut feedback, "0-9-1".into());
+                    },
                     Some(Ok(frame)) => {
                         notify_waiter(&frame, &mut feedback)?;
 
-                        if let Ok(Some(response)) = handle_in_frame(&frame, &mut client).await {
-                            sink.send(response).await?
+                        let event = match client.receive_frame(frame) {
+                            Ok(Some(event)) => event,
+                            Ok(None) => continue,
+                            Err(e) => match e.downcast::<ProtocolError>() {
+                                Ok(pe) => {
+                                    error!("Server broke the protocol {}", pe);
+
+                                    let close = pe.close_frame();
+
+                                    client.send_frame(&close)?;
+                                    framed.send(close).await?;
+
+                                    continue;
+                                }
+                                Err(e) => return Err(e),
+                            },
+                        };
+
+                        if let Ok(Some(response)) = handle_in_event(event, &mut client).await {
+                            let tune_ok = matches!(response, AMQPFrame::Method(_, frame::CONNECTION_TUNE_OK, _));
+
+                            client.send_frame(&response)?;
+                            framed.send(response).await?;
+
+                            if tune_ok {
+                                framed.codec_mut().set_frame_max(client.frame_max());
+                                heartbeat = heartbeat_timer(client.heartbeat());
+                            }
+                        }
+                    },
+                    Some(Err(e)) => {
+                        if let Some(he) = e.downcast_ref::<ProtocolHeaderError>() {
+                            let version = he.version().unwrap_or_else(|| "non-AMQP".into());
+
+                            return reject_protocol(&mut feedback, version);
                         }
+
+                        error!("Cannot decode frame {:?}", e);
+
+                        return Err(e)
                     },
-                    Some(Err(e)) =>
-                        error!("Handle errors {:?}", e),
                     None => {
                         return Ok(())
                     }
                 }
             }
+            _ = tick(&mut heartbeat) => {
+                framed.send(AMQPFrame::Heartbeat(0)).await?;
+            }
             Some(request) = receiver.recv() => {
                 match request.param {
-                    Param::Frame(AMQPFrame::Header) => {
-                        register_waiter(&mut feedback, Some(0), request.response);
-                        sink.send(AMQPFrame::Header).await?;
-                    },
+                    Param::Frame(AMQPFrame::Header) =>
+                        send_request(&mut framed, &mut client, &mut feedback, vec![AMQPFrame::Header], request.response).await?,
                     Param::Frame(AMQPFrame::Method(ch, _, ma)) =>
-                        if let Some(response) = handle_out_frame(ch, ma, &mut client).await? {
-                            let resp_channel = channel(&response);
-                            sink.send(response).await?;
-                            register_waiter(&mut feedback, resp_channel, request.response);
+                        match handle_out_frame(ch, ma, &mut client).await {
+                            Ok(Some(response)) =>
+                                send_request(&mut framed, &mut client, &mut feedback, vec![response], request.response).await?,
+                            Ok(None) => (),
+                            Err(e) => reject_request(e, request.response)?,
                         },
                     Param::Consume(AMQPFrame::Method(ch, _, MethodFrameArgs::BasicConsume(args)), msg_sink) =>
                         if let Some(response) = client.basic_consume(ch, &args, msg_sink).await? {
-                            let resp_channel = channel(&response);
-                            sink.send(response).await?;
-                            register_waiter(&mut feedback, resp_channel, request.response);
-                        },
-                    Param::Publish(AMQPFrame::Method(ch, _, MethodFrameArgs::BasicPublish(args)), content) =>
-                        for response in handle_publish(ch, args, content, &mut client).await? {
-                            sink.send(response).await?;
+                            send_request(&mut framed, &mut client, &mut feedback, vec![response], request.response).await?;
                         },
+                    Param::Publish(AMQPFrame::Method(ch, _, MethodFrameArgs::BasicPublish(args)), content) => {
+                        // publish, header and body frames go out with one flush
+                        let frames = handle_publish(ch, args, content, &mut client).await?;
+                        send_request(&mut framed, &mut client, &mut feedback, frames, request.response).await?;
+                    },
                     _ =>
                         unreachable!("{:?}", request)
                 }
@@ -111,6 +160,97 @@ async fn socket_loop(socket: TcpStream, mut receiver: mpsc::Receiver<Request>) -
     }
 }
 
+/// Heartbeats are sent at the half of the negotiated interval, zero means no heartbeats.
+fn heartbeat_timer(seconds: u16) -> Option<time::Interval> {
+    if seconds == 0 {
+        return None;
+    }
+
+    let period = Duration::from_secs(seconds as u64) / 2;
+
+    Some(time::interval_at(time::Instant::now() + period, period))
+}
+
+/// Wait for the next heartbeat, if heartbeats are off it never returns.
+async fn tick(timer: &mut Option<time::Interval>) {
+    match timer {
+        Some(t) => {
+            t.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Send the frames of a request and register the caller to wait for the answer. If the frames are
+/// not allowed in the current state of the connection or they cannot be encoded, nothing is sent
+/// and the caller gets the error. Publish doesn't have an answer, so the caller is unblocked as
+/// soon as the frames are sent.
+async fn send_request(
+    framed: &mut Framed<TcpStream, AMQPCodec>,
+    client: &mut ClientState,
+    feedback: &mut HashMap<u16, Response>,
+    frames: Vec<AMQPFrame>,
+    response: Option<Response>
+) -> Result<()> {
+    let (resp_channel, cm) = match &frames[0] {
+        AMQPFrame::Method(ch, cm, _) => (Some(*ch), *cm),
+        f => (channel(f), 0),
+    };
+    let publish = cm == frame::BASIC_PUBLISH;
+
+    let result = match frames.iter().try_for_each(|f| client.send_frame(f)) {
+        Ok(()) => framed.send(frames).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) if publish => {
+            if let Some(r) = response {
+                let _ = r.send(Ok(()));
+            }
+        },
+        Ok(()) =>
+            register_waiter(feedback, resp_channel, response),
+        Err(e) => {
+            let err = request_error(resp_channel, cm, e)?;
+
+            match response {
+                Some(r) => {
+                    let _ = r.send(Err(Box::new(err)));
+                },
+                None =>
+                    error!("Request is not sent {}", err)
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Protocol errors and frame errors of the encoder concern only the request, the connection can
+/// go on. Other errors are given back.
+fn request_error(channel: Option<frame::Channel>, cm: frame::ClassMethod, err: Error) -> Result<crate::ClientError> {
+    if let Some(pe) = err.downcast_ref::<ProtocolError>() {
+        return Ok(crate::ClientError {
+            channel: Some(pe.channel),
+            code: pe.code,
+            message: pe.text.clone(),
+            class_method: pe.class_method
+        });
+    }
+
+    if let Some(fe) = err.downcast_ref::<FrameError>() {
+        return Ok(crate::ClientError {
+            channel,
+            code: reply_code(fe.code),
+            message: fe.message.clone(),
+            class_method: cm
+        });
+    }
+
+    Err(err)
+}
+
 /// Unblock the client by sending a `Response`. If there is no error on the channel or
 /// in the connection the result will be a unit type. If there is an AMQP channel error,
 /// it sends back to the client call who is blocked on that channel, so the client API
@@ -122,8 +262,8 @@ fn notify_waiter(frame: &AMQPFrame, feedback: &mut HashMap<u16, Response>) -> Re
         AMQPFrame::Method(_, frame::CONNECTION_CLOSE, MethodFrameArgs::ConnectionClose(args)) => {
             let err = crate::ClientError {
                 channel: None,
-                code: args.code,
-                message: args.text.clone(),
+                code: reply_code(args.reply_code),
+                message: args.reply_text.clone(),
                 class_method: frame::unify_class_method(args.class_id, args.method_id)
             };
 
@@ -136,11 +276,11 @@ fn notify_waiter(frame: &AMQPFrame, feedback: &mut HashMap<u16, Response>) -> Re
             Ok(())
         },
         AMQPFrame::Method(channel, frame::CHANNEL_CLOSE, MethodFrameArgs::ChannelClose(args)) => {
-            let err: Result<()> = client_error!(Some(*channel), args.code, args.text.clone(), frame::unify_class_method(args.class_id, args.method_id));
+            let err: Result<()> = client_error!(Some(*channel), reply_code(args.reply_code), args.reply_text.clone(), frame::unify_class_method(args.class_id, args.method_id));
 
             if let Some(fb) = feedback.remove(&channel) {
                 if let Err(_) = fb.send(err) {
-                    return client_error!(None, 501, "Cannot unblock client", 0)
+                    return client_error!(None, ReplyCode::InternalError, "Cannot unblock client", 0)
                 }
             }
             Ok(())
@@ -148,7 +288,7 @@ fn notify_waiter(frame: &AMQPFrame, feedback: &mut HashMap<u16, Response>) -> Re
         AMQPFrame::Method(channel, _, _) => {
             if let Some(fb) = feedback.remove(&channel) {
                 if let Err(_) = fb.send(Ok(())) {
-                    return client_error!(None, 501, "Cannot unblock client", 0)
+                    return client_error!(None, ReplyCode::InternalError, "Cannot unblock client", 0)
                 }
             }
             Ok(())
@@ -158,6 +298,50 @@ fn notify_waiter(frame: &AMQPFrame, feedback: &mut HashMap<u16, Response>) -> Re
     }
 }
 
+/// Reply code the server sent in a close method. Codes which are not in the spec are reported as
+/// internal errors.
+fn reply_code(code: u16) -> ReplyCode {
+    ReplyCode::from_code(code).unwrap_or(ReplyCode::InternalError)
+}
+
+/// The server rejected our protocol version and closes the connection. Waiters get the error
+/// and the version the server supports.
+fn reject_protocol(feedback: &mut HashMap<u16, Response>, server_version: String) -> Result<()> {
+    let err = crate::ClientError {
+        channel: None,
+        code: ReplyCode::NotImplemented,
+        message: format!("Server doesn't support AMQP 0-9-1, it supports {}", server_version),
+        class_method: 0,
+    };
+
+    error!("{}", err.message);
+
+    for (_, fb) in feedback.drain() {
+        let _ = fb.send(Err(Box::new(err.clone())));
+    }
+
+    Err(Box::new(err))
+}
+
+/// The request could not be sent, the caller gets the `ClientError`. Other errors are given
+/// back, since they are problems of the connection.
+fn reject_request(err: Error, response: Option<Response>) -> Result<()> {
+    match (err.downcast::<crate::ClientError>(), response) {
+        (Ok(ce), Some(r)) => {
+            let err: Error = ce;
+            let _ = r.send(Err(err));
+
+            Ok(())
+        }
+        (Ok(ce), None) => {
+            error!("Request is not sent {}", ce);
+
+            Ok(())
+        }
+        (Err(e), _) => Err(e),
+    }
+}
+
 fn register_waiter(feedback: &mut HashMap<u16, Response>, channel: Option<frame::Channel>, response_channel: Option<Response>) {
     if let Some(ch) = channel {
         if let Some(chan) = response_channel {
@@ -175,18 +359,13 @@ fn channel(f: &AMQPFrame) -> Option<u16> {
     }
 }
 
-async fn handle_in_frame(f: &AMQPFrame, cs: &mut ClientState) -> Result<Option<AMQPFrame>> {
-    debug!("Incoming {:?}", f);
+async fn handle_in_event(event: Event, cs: &mut ClientState) -> Result<Option<AMQPFrame>> {
+    debug!("Incoming {:?}", event);
 
-    match f {
-        AMQPFrame::Header => Ok(None),
-        AMQPFrame::Method(ch, _, args) => {
-            // TODO copy happens? check with a small poc
-            handle_in_method_frame(*ch, args, cs).await
-        }
-        AMQPFrame::ContentHeader(ch) => cs.content_header(ch).await,
-        AMQPFrame::ContentBody(cb) => cs.content_body(cb).await,
-        AMQPFrame::Heartbeat(_) => Ok(None),
+    match event {
+        Event::Method(ch, _, args) => handle_in_method_frame(ch, &args, cs).await,
+        Event::Content(ch, _, MethodFrameArgs::BasicDeliver(args), content) => cs.basic_deliver(ch, &args, *content).await,
+        _ => Ok(None),
     }
 }
 
@@ -198,7 +377,11 @@ async fn handle_in_method_frame(
 ) -> Result<Option<AMQPFrame>> {
     match ma {
         MethodFrameArgs::ConnectionStart(args) => cs.connection_start(args).await,
+        MethodFrameArgs::ConnectionSecure(args) => cs.connection_secure(args).await,
         MethodFrameArgs::ConnectionTune(args) => cs.connection_tune(args).await,
+        MethodFrameArgs::ConnectionBlocked(args) => cs.connection_blocked(args).await,
+        MethodFrameArgs::ConnectionUnblocked => cs.connection_unblocked().await,
+        MethodFrameArgs::ConnectionUpdateSecretOk => cs.connection_update_secret_ok().await,
         MethodFrameArgs::ConnectionOpenOk => cs.connection_open_ok().await,
         MethodFrameArgs::ConnectionClose(args) => cs.handle_connection_close(args).await,
         MethodFrameArgs::ChannelOpenOk => cs.channel_open_ok(channel).await,
@@ -209,10 +392,13 @@ async fn handle_in_method_frame(
         MethodFrameArgs::QueueBindOk => cs.queue_bind_ok().await,
         MethodFrameArgs::ConnectionCloseOk => cs.connection_close_ok().await,
         MethodFrameArgs::BasicConsumeOk(args) => cs.basic_consume_ok(args).await,
-        MethodFrameArgs::BasicDeliver(args) => cs.basic_deliver(channel, args).await,
         MethodFrameArgs::ChannelClose(args) => cs.handle_channel_close(channel, args).await,
         //    // TODO check if client is consuming messages from that channel + consumer tag
-        _ => unimplemented!("{:?}", ma),
+        _ => {
+            warn!("Unhandled method from the server {:?}", ma);
+
+            Ok(None)
+        }
     }
 }
 
@@ -224,44 +410,40 @@ async fn handle_out_frame(channel: frame::Channel, ma: MethodFrameArgs, cs: &mut
         MethodFrameArgs::ConnectionTuneOk(args) => cs.connection_tune_ok(&args).await,
         MethodFrameArgs::ConnectionOpen(args) => cs.connection_open(&args).await,
         MethodFrameArgs::ConnectionClose(args) => cs.connection_close(&args).await,
+        MethodFrameArgs::ConnectionUpdateSecret(args) => cs.connection_update_secret(&args).await,
         MethodFrameArgs::ChannelOpen => cs.channel_open(channel).await,
         MethodFrameArgs::ChannelClose(args) => cs.channel_close(channel, &args).await,
         MethodFrameArgs::ExchangeDeclare(args) => cs.exchange_declare(channel, &args).await,
         MethodFrameArgs::QueueDeclare(args) => cs.queue_declare(channel, &args).await,
         MethodFrameArgs::QueueBind(args) => cs.queue_bind(channel, &args).await,
         MethodFrameArgs::BasicPublish(args) => cs.basic_publish(channel, &args).await,
-        _ => unimplemented!()
+        _ => client_error!(Some(channel), ReplyCode::NotImplemented, format!("Client cannot send {:?}", ma), 0),
     }
 }
 
 async fn handle_publish(
     channel: frame::Channel,
-    args: frame::BasicPublishArgs, content: Vec<u8>,
+    args: frame::BasicPublishArgs, content: Bytes,
     cs: &mut ClientState
 ) -> Result<Vec<AMQPFrame>> {
     match cs.basic_publish(channel, &args).await? {
-        Some(publish_frame) =>
-            Ok(vec![
+        Some(publish_frame) => {
+            let mut frames = vec![
                 publish_frame,
                 AMQPFrame::ContentHeader(frame::content_header(channel, content.len() as u64)),
-                AMQPFrame::ContentBody(frame::content_body(channel, content.as_slice()))
-            ]),
+            ];
+
+            for body in content_body_frames(channel, &content, cs.frame_max()) {
+                frames.push(AMQPFrame::ContentBody(body));
+            }
+
+            Ok(frames)
+        },
         None =>
             unreachable!()
     }
 }
 
-pub(crate) async fn call(conn: &Client, frame: AMQPFrame) -> Result<()> {
-    conn.server_channel
-        .send(Request {
-            param: Param::Frame(frame),
-            response: None,
-        })
-        .await?;
-
-    Ok(())
-}
-
 pub(crate) async fn sync_call(conn: &Client, frame: AMQPFrame) -> Result<()> {
     let (tx, rx) = oneshot::channel();
 
@@ -275,6 +457,6 @@ pub(crate) async fn sync_call(conn: &Client, frame: AMQPFrame) -> Result<()> {
     match rx.await {
         Ok(Ok(())) => Ok(()),
         Ok(Err(e)) => Err(e),
-        Err(_) => client_error!(None, 0, "Channel recv error", 0),
+        Err(_) => client_error!(None, ReplyCode::InternalError, "Channel recv error", 0),
     }
 }
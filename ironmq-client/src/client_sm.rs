@@ -6,47 +6,32 @@
 //! AMQP frame or `MethodFrame`, content etc. Everything which talks to the client
 //! api it is a typed struct.
 
-use crate::{Message, MessageSink, Result};
-use ironmq_codec::frame::{self, Channel};
-use log::{debug, info};
+use crate::{Message, MessageSink, ReplyCode, Result};
+use ironmq_codec::content::Content;
+use ironmq_codec::frame::{self, AMQPFrame, Channel};
+use ironmq_codec::protocol::{Event, Protocol, Role};
+use log::{debug, info, warn};
 use std::collections::HashMap;
 use std::fmt;
 
-#[derive(Debug)]
-enum Phase {
-    Uninitialized,
-    Connected,
-    Authenticated,
-    //    Closing
-}
-
-#[derive(Debug)]
-struct DeliveredContent {
-    channel: u16,
-    consumer_tag: String,
-    delivery_tag: u64,
-    exchange_name: String,
-    routing_key: String,
-    body_size: Option<u64>,
-    body: Option<Vec<u8>>
-}
-
 // TODO basic consume subscribe to a queue but when messages are delivered we get only the exchange
 // name
 pub(crate) struct ClientState {
-    state: Phase,
     username: String,
     password: String,
     consumers: HashMap<Channel, MessageSink>,
-    in_delivery: HashMap<Channel, DeliveredContent>,
+    /// Checks the order of the frames, tracks the channels and collects the contents.
+    protocol: Protocol,
+    /// The reason if the server blocked the connection.
+    blocked: Option<String>,
 }
 
 impl fmt::Debug for ClientState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "ClientState {{ state={:?}, username={}, password={} }}",
-            &self.state, &self.username, &self.password
+            "ClientState {{ phase={:?}, username={}, password=*** }}",
+            self.protocol.phase(), &self.username
         )
     }
 }
@@ -59,33 +44,50 @@ type MaybeFrame = Result<Option<frame::AMQPFrame>>;
 
 pub(crate) fn new() -> ClientState {
     ClientState {
-        state: Phase::Uninitialized,
         username: "guest".into(),
         password: "guest".into(),
         consumers: HashMap::new(),
-        in_delivery: HashMap::new(),
+        protocol: Protocol::new(Role::Client),
+        blocked: None,
     }
 }
 
 impl ClientState {
+    pub(crate) fn frame_max(&self) -> u32 {
+        self.protocol.frame_max()
+    }
+
+    /// Negotiated heartbeat interval in seconds.
+    pub(crate) fn heartbeat(&self) -> u16 {
+        self.protocol.heartbeat()
+    }
+
+    /// Check the frame of the server, see `Protocol::receive_frame`.
+    pub(crate) fn receive_frame(&mut self, f: AMQPFrame) -> Result<Option<Event>> {
+        self.protocol.receive_frame(f)
+    }
+
+    /// Check the frame before it is sent to the server, see `Protocol::send_frame`.
+    pub(crate) fn send_frame(&mut self, f: &AMQPFrame) -> Result<()> {
+        self.protocol.send_frame(f)
+    }
+
     pub(crate) async fn connection_start(&mut self, args: &frame::ConnectionStartArgs) -> MaybeFrame {
-        info!("Server supported mechanisms: {}", args.mechanisms);
+        info!("Server supported mechanisms: {}", String::from_utf8_lossy(&args.mechanisms));
         // TODO here we need to send start_ok not in the other function
         Ok(None)
     }
 
     pub(crate) async fn connection_start_ok(&mut self, _args: &frame::ConnectionStartOkArgs) -> MaybeFrame {
-        self.state = Phase::Connected;
-
         let mut caps = frame::FieldTable::new();
 
         caps.insert(
-            "authentication_failure_on_close".into(),
+            "authentication_failure_close".into(),
             frame::AMQPFieldValue::Bool(true),
         );
 
         //capabilities.insert("basic.nack".into(), AMQPFieldValue::Bool(true));
-        //capabilities.insert("connection.blocked".into(), AMQPFieldValue::Bool(true));
+        caps.insert("connection.blocked".into(), frame::AMQPFieldValue::Bool(true));
         //capabilities.insert("consumer_cancel_notify".into(), AMQPFieldValue::Bool(true));
         //capabilities.insert("pub(crate)lisher_confirms".into(), AMQPFieldValue::Bool(true));
 
@@ -96,10 +98,17 @@ impl ClientState {
         )))
     }
 
-    pub(crate) async fn connection_tune(&mut self, _args: &frame::ConnectionTuneArgs) -> MaybeFrame {
-        self.state = Phase::Authenticated;
+    /// We support only PLAIN, so the answer for a challenge is the same credentials.
+    pub(crate) async fn connection_secure(&mut self, _args: &frame::ConnectionSecureArgs) -> MaybeFrame {
+        let response = format!("\u{0}{}\u{0}{}", self.username, self.password);
 
-        Ok(Some(frame::connection_tune_ok(0)))
+        Ok(Some(frame::connection_secure_ok(0, response.as_bytes())))
+    }
+
+    /// Accept the limits of the server, the client doesn't go above them. The protocol state
+    /// machine negotiates them when the tune ok is sent.
+    pub(crate) async fn connection_tune(&mut self, args: &frame::ConnectionTuneArgs) -> MaybeFrame {
+        Ok(Some(frame::connection_tune_ok(0, args.channel_max, args.frame_max, args.heartbeat)))
     }
 
     pub(crate) async fn connection_tune_ok(&mut self, _args: &frame::ConnectionTuneOkArgs) -> MaybeFrame {
@@ -114,8 +123,34 @@ impl ClientState {
         Ok(None)
     }
 
+    pub(crate) async fn connection_blocked(&mut self, args: &frame::ConnectionBlockedArgs) -> MaybeFrame {
+        warn!("Server blocked the connection: {}", args.reason);
+
+        self.blocked = Some(args.reason.clone());
+
+        Ok(None)
+    }
+
+    pub(crate) async fn connection_unblocked(&mut self) -> MaybeFrame {
+        if self.blocked.take().is_some() {
+            info!("Server unblocked the connection");
+        }
+
+        Ok(None)
+    }
+
+    pub(crate) async fn connection_update_secret(&mut self, args: &frame::ConnectionUpdateSecretArgs) -> MaybeFrame {
+        self.password = String::from_utf8_lossy(&args.new_secret).into_owned();
+
+        Ok(Some(frame::connection_update_secret(0, &args.new_secret, &args.reason)))
+    }
+
+    pub(crate) async fn connection_update_secret_ok(&mut self) -> MaybeFrame {
+        Ok(None)
+    }
+
     pub(crate) async fn connection_close(&mut self, _args: &frame::ConnectionCloseArgs) -> MaybeFrame {
-        Ok(Some(frame::connection_close(0, 200, "Normal close", 0, 0)))
+        Ok(Some(frame::connection_close(0, ReplyCode::ReplySuccess.into(), "Normal close", 0, 0)))
     }
 
     pub(crate) async fn connection_close_ok(&mut self) -> MaybeFrame {
@@ -136,7 +171,7 @@ impl ClientState {
     }
 
     pub(crate) async fn channel_close(&mut self, channel: Channel, args: &frame::ChannelCloseArgs) -> MaybeFrame {
-        Ok(Some(frame::channel_close(channel, args.code, &args.text, args.class_id, args.method_id)))
+        Ok(Some(frame::channel_close(channel, args.reply_code, &args.reply_text, args.class_id, args.method_id)))
     }
 
     pub(crate) async fn channel_close_ok(&mut self, channel: Channel) -> MaybeFrame {
@@ -147,14 +182,18 @@ impl ClientState {
         Ok(None)
     }
 
-    pub(crate) async fn handle_channel_close(&mut self, _channel: Channel, _args: &frame::ChannelCloseArgs) -> MaybeFrame {
-        // TODO handle that the server closed the channel
-        //Ok(Some(frame::channel_close_ok(channel)))
-        Ok(None)
+    /// The server closed the channel because of an error. The close-ok frees the channel on both
+    /// sides, so the channel number can be opened again.
+    pub(crate) async fn handle_channel_close(&mut self, channel: Channel, _args: &frame::ChannelCloseArgs) -> MaybeFrame {
+        if let Some(sink) = self.consumers.remove(&channel) {
+            drop(sink);
+        }
+
+        Ok(Some(frame::channel_close_ok(channel)))
     }
 
     pub(crate) async fn exchange_declare(&mut self, channel: Channel, args: &frame::ExchangeDeclareArgs) -> MaybeFrame {
-        Ok(Some(frame::exchange_declare(channel, &args.exchange_name, &args.exchange_type, Some(args.flags))))
+        Ok(Some(frame::exchange_declare(channel, &args.exchange, &args.exchange_type, Some(args.flags))))
     }
 
     pub(crate) async fn exchange_declare_ok(&mut self) -> MaybeFrame {
@@ -170,7 +209,7 @@ impl ClientState {
     }
 
     pub(crate) async fn queue_declare(&mut self, channel: Channel, args: &frame::QueueDeclareArgs) -> MaybeFrame {
-        Ok(Some(frame::queue_declare(channel, &args.name)))
+        Ok(Some(frame::queue_declare(channel, &args.queue)))
     }
 
     pub(crate) async fn queue_declare_ok(&mut self, _args: &frame::QueueDeclareOkArgs) -> MaybeFrame {
@@ -178,7 +217,7 @@ impl ClientState {
     }
 
     pub(crate) async fn queue_bind(&mut self, channel: Channel, args: &frame::QueueBindArgs) -> MaybeFrame {
-        Ok(Some(frame::queue_bind(channel, &args.queue_name, &args.exchange_name, &args.routing_key)))
+        Ok(Some(frame::queue_bind(channel, &args.queue, &args.exchange, &args.routing_key)))
     }
 
     pub(crate) async fn queue_bind_ok(&mut self) -> MaybeFrame {
@@ -195,57 +234,25 @@ impl ClientState {
         Ok(None)
     }
 
-    pub(crate) async fn basic_deliver(&mut self, channel: Channel, args: &frame::BasicDeliverArgs) -> MaybeFrame {
-        let dc = DeliveredContent {
-            channel: channel,
-            consumer_tag: args.consumer_tag.clone(),
-            delivery_tag: args.delivery_tag,
-            exchange_name: args.exchange_name.clone(),
-            routing_key: args.routing_key.clone(),
-            body_size: None,
-            body: None
-        };
-
-        self.in_delivery.insert(channel, dc);
-
-        Ok(None)
-    }
-
-    pub(crate) async fn basic_publish(&mut self, channel: Channel, args: &frame::BasicPublishArgs) -> MaybeFrame {
-        Ok(Some(frame::basic_publish(channel, &args.exchange_name, &args.routing_key)))
-    }
+    /// Send the delivered content to the consumer of the channel.
+    pub(crate) async fn basic_deliver(&mut self, channel: Channel, args: &frame::BasicDeliverArgs, content: Content) -> MaybeFrame {
+        debug!("Delivered content {:?} with tag {}", args.consumer_tag, args.delivery_tag);
 
-    pub(crate) async fn content_header(&mut self, ch: &frame::ContentHeaderFrame) -> MaybeFrame {
-        info!("Content header arrived {:?}", ch);
+        if let Some(sink) = self.consumers.get(&channel) {
+            let msg = Message {
+                channel,
+                length: content.body.len(),
+                properties: content.header.properties,
+                body: content.body,
+            };
 
-        if let Some(dc) = self.in_delivery.get_mut(&ch.channel) {
-            dc.body_size = Some(ch.body_size);
+            sink.send(msg).await?
         }
 
-        // TODO error handling
-
         Ok(None)
     }
 
-    pub(crate) async fn content_body(&mut self, cb: &frame::ContentBodyFrame) -> MaybeFrame {
-        info!("Content body arrived {:?}", cb);
-
-        if let Some(dc) = self.in_delivery.get(&cb.channel) {
-            debug!("Delivered content is {:?} so far", dc);
-
-            debug!("Consumers {:?}", self.consumers);
-
-            if let Some(sink) = self.consumers.get(&dc.channel) {
-                let msg = Message {
-                    channel: dc.channel,
-                    body: cb.body.clone(),
-                    length: dc.body_size.unwrap() as usize
-                };
-
-                sink.send(msg).await?
-            }
-        }
-
-        Ok(None)
+    pub(crate) async fn basic_publish(&mut self, channel: Channel, args: &frame::BasicPublishArgs) -> MaybeFrame {
+        Ok(Some(frame::basic_publish(channel, &args.exchange, &args.routing_key)))
     }
 }
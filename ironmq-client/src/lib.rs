@@ -17,9 +17,9 @@
 pub mod client;
 mod client_sm;
 
+use bytes::Bytes;
 use env_logger::Builder;
 use ironmq_codec::frame;
-use std::collections::HashMap;
 use std::fmt;
 use std::io::Write;
 use std::time::Instant;
@@ -31,6 +31,8 @@ pub type Channel = frame::Channel;
 pub type ClassId = frame::ClassId;
 /// AMQP class id method id number
 pub type ClassMethod = frame::ClassMethod;
+/// AMQP reply code of connection and channel errors
+pub type ReplyCode = frame::ReplyCode;
 
 // TODO feature log should log in trace level
 
@@ -47,7 +49,9 @@ pub type MessageSink = mpsc::Sender<Message>;
 #[derive(Debug)]
 pub struct Message {
     pub channel: Channel,
-    pub body: Vec<u8>,
+    /// Properties of the message like content type, headers or correlation id.
+    pub properties: frame::BasicProperties,
+    pub body: Bytes,
     pub length: usize
 }
 
@@ -56,7 +60,7 @@ pub struct Message {
 #[derive(Clone, Debug)]
 pub struct ClientError {
     pub channel: Option<Channel>,
-    pub code: u16,
+    pub code: ReplyCode,
     pub message: String,
     pub class_method: u32
 }
@@ -91,7 +95,8 @@ pub struct Client {
 
 /// Connect to an AMQP server.
 ///
-/// This is async code and wait for the [`ironmq_codec::frame::ConnectionTuneOkArgs`] message.
+/// This is async code and wait for the [`ironmq_codec::frame::ConnectionTuneArgs`] message, the
+/// client answers it with the limits of the server.
 ///
 /// ```no_run
 /// async fn connect() -> ironmq_client::Result<()> {
@@ -103,8 +108,7 @@ pub async fn connect(url: &str) -> Result<Client> {
     let connection = client::create_connection(url.into()).await?;
 
     client::sync_call(&connection, frame::AMQPFrame::Header).await?;
-    client::sync_call(&connection, frame::connection_start_ok("guest", "guest", HashMap::new())) .await?;
-    client::call(&connection, frame::connection_tune_ok(0)).await?;
+    client::sync_call(&connection, frame::connection_start_ok("guest", "guest", frame::FieldTable::new())) .await?;
 
     Ok(connection)
 }
@@ -126,8 +130,14 @@ impl Client {
         client::sync_call(&self, frame::connection_open(0, virtual_host.into())).await
     }
 
+    /// Send new credentials to the server, for example when the token of the connection is about
+    /// to expire. The call waits for the `update-secret-ok` of the server.
+    pub async fn update_secret(&self, new_secret: &str, reason: &str) -> Result<()> {
+        client::sync_call(self, frame::connection_update_secret(0, new_secret.as_bytes(), reason)).await
+    }
+
     pub async fn close(&self) -> Result<()> {
-        client::sync_call(&self, frame::connection_close(0, 200, "Normal close", 0, 0)).await
+        client::sync_call(&self, frame::connection_close(0, ReplyCode::ReplySuccess.into(), "Normal close", 0, 0)).await
     }
 
     pub async fn channel_open(&self, channel: u16) -> Result<()> {
@@ -136,8 +146,9 @@ impl Client {
 
     pub async fn channel_close(&self, channel: Channel) -> Result<()> {
         let (cid, mid) = frame::split_class_method(frame::CHANNEL_CLOSE);
+        let frame = frame::channel_close(channel, ReplyCode::ReplySuccess.into(), "Normal close", cid, mid);
 
-        client::sync_call(&self, frame::channel_close(channel, 200, "Normal close", cid, mid)).await
+        client::sync_call(&self, frame).await
     }
 
     pub async fn exchange_declare(&self, channel: Channel, exchange_name: &str,
@@ -175,20 +186,27 @@ impl Client {
                 Ok(()) => Ok(()),
                 Err(e) => Err(e)
             },
-            Err(_) => client_error!(None, 501, "Channel recv error", 0)
+            Err(_) => client_error!(None, ReplyCode::InternalError, "Channel recv error", 0)
         }
     }
 
+    /// Publish a message. The call returns when the message is sent, there is no answer from the
+    /// server. Frames which cannot be encoded, like a routing key longer than 255 bytes, are given
+    /// back as errors and nothing is sent.
     pub async fn basic_publish(&self, channel: Channel, exchange_name: &str, routing_key: &str,
                            payload: String) -> Result<()> {
         let frame = frame::basic_publish(channel, exchange_name.into(), routing_key.into());
+        let (tx, rx) = oneshot::channel();
 
         self.server_channel.send(client::Request {
-            param: client::Param::Publish(frame, payload.as_bytes().to_vec()),
-            response: None
+            param: client::Param::Publish(frame, Bytes::from(payload)),
+            response: Some(tx)
         }).await?;
 
-        Ok(())
+        match rx.await {
+            Ok(response) => response,
+            Err(_) => client_error!(None, ReplyCode::InternalError, "Channel recv error", 0)
+        }
     }
 }
 
@@ -228,7 +246,7 @@ mod tests {
     async fn send_client_error() {
         let (tx, rx) = tokio::sync::oneshot::channel::<Result<()>>();
 
-        tx.send(client_error!(None, 404, "Not found", 0)).unwrap();
+        tx.send(client_error!(None, ReplyCode::NotFound, "Not found", 0)).unwrap();
 
         let res = rx.await.unwrap();
 
@@ -236,7 +254,7 @@ mod tests {
 
         let err = res.unwrap_err().downcast::<ClientError>().unwrap();
         assert_eq!(err.channel, None);
-        assert_eq!(err.code, 404);
+        assert_eq!(err.code, ReplyCode::NotFound);
         assert_eq!(err.message, "Not found".to_string());
         assert_eq!(err.class_method, 0);
     }
@@ -23,7 +23,7 @@ async fn can_connect() -> Result<()> {
 
     let err = result.unwrap_err().downcast::<ClientError>().unwrap();
     assert_eq!(err.channel, None);
-    assert_eq!(err.code, 530);
+    assert_eq!(err.code, ReplyCode::NotAllowed);
     assert_eq!(err.class_method, ironmq_codec::frame::CONNECTION_OPEN);
 
     Ok(())
@@ -43,7 +43,7 @@ async fn double_open_the_same_channel() -> Result<()> {
 
     let err = result.unwrap_err().downcast::<ClientError>().unwrap();
     assert_eq!(err.channel, Some(1));
-    assert_eq!(err.code, 504);
+    assert_eq!(err.code, ReplyCode::ChannelError);
     assert_eq!(err.class_method, ironmq_codec::frame::CONNECTION_OPEN);
 
     Ok(())
@@ -69,3 +69,25 @@ async fn helper(exchange: &str, queue: &str) -> Result<Box<dyn Client>> {
 
     Ok(c)
 }
+
+#[tokio::test]
+async fn rejected_protocol_version_is_reported() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    std::thread::spawn(move || {
+        use std::io::{Read, Write};
+
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut header = [0u8; 8];
+
+        socket.read_exact(&mut header).unwrap();
+        socket.write_all(b"AMQP\x01\x01\x00\x0A").unwrap();
+    });
+
+    let result = connect(&addr).await;
+
+    let err = result.err().unwrap().downcast::<ClientError>().unwrap();
+    assert_eq!(err.code, ReplyCode::NotImplemented);
+    assert!(err.message.contains("1-0-10"));
+}
@@ -0,0 +1,673 @@
+//! Generates the method frame layer from the AMQP 0-9-1 spec in `spec/amqp0-9-1.xml`.
+//!
+//! The spec file is read as it is published, the RabbitMQ extensions are kept apart in
+//! `spec/rabbitmq-extensions.xml`. An extension method replaces the spec method of the same class
+//! and name, the other extension methods are appended to the spec methods.
+//!
+//! Two files are written to `OUT_DIR`:
+//!
+//! * `frame_methods.rs` is included in the `frame` module. It contains the spec constants, the
+//!   class ids, the class-method constants, the argument structs, the bit flags and the
+//!   `MethodFrameArgs` enum.
+//! * `codec_methods.rs` is included in the `codec` module. It contains the encoder and the decoder
+//!   of the method arguments.
+//!
+//! Reserved fields are not part of the argument structs, they are written as zero values and
+//! skipped during decoding. Consecutive bit fields are packed into one octet. If a method has more
+//! than one bit field, the bits are represented by a bitflags type, otherwise by a `bool`.
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+const SPEC: &str = "spec/amqp0-9-1.xml";
+const EXTENSIONS: &str = "spec/rabbitmq-extensions.xml";
+
+/// Generated types can be serialized if the `serde` feature is on.
+const SERDE_DERIVE: &str = "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]";
+
+struct Constant {
+    name: String,
+    value: u32,
+    reply_code: bool,
+    /// `soft-error` or `hard-error` for the error reply codes.
+    class: Option<String>,
+}
+
+struct Field {
+    name: String,
+    /// Base type of the domain: bit, octet, short, long, longlong, shortstr, longstr, timestamp,
+    /// table.
+    base_type: String,
+    reserved: bool,
+    /// Position in the packed octet if this is a bit field.
+    bit: Option<u8>,
+}
+
+struct Method {
+    class_name: String,
+    name: String,
+    class_method: u32,
+    fields: Vec<Field>,
+}
+
+impl Method {
+    fn const_name(&self) -> String {
+        format!("{}_{}", upper_snake(&self.class_name), upper_snake(&self.name))
+    }
+
+    fn type_name(&self) -> String {
+        format!("{}{}", camel(&self.class_name), camel(&self.name))
+    }
+
+    fn spec_name(&self) -> String {
+        format!("{}.{}", self.class_name, self.name)
+    }
+
+    fn args(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(|f| !f.reserved)
+    }
+
+    fn has_args(&self) -> bool {
+        self.args().next().is_some()
+    }
+
+    fn bits(&self) -> Vec<&Field> {
+        self.args().filter(|f| f.bit.is_some()).collect()
+    }
+
+    fn has_flags(&self) -> bool {
+        self.bits().len() > 1
+    }
+
+    /// Name of the struct field, `type` is a keyword so it is prefixed by the class name.
+    fn field_name(&self, field: &Field) -> String {
+        match field.name.as_str() {
+            "type" => format!("{}_type", snake(&self.class_name)),
+            name => snake(name),
+        }
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", SPEC);
+    println!("cargo:rerun-if-changed={}", EXTENSIONS);
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let spec_xml = fs::read_to_string(SPEC).expect("Cannot read AMQP spec");
+    let ext_xml = fs::read_to_string(EXTENSIONS).expect("Cannot read RabbitMQ extensions");
+
+    // the published spec has a DOCTYPE declaration
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..roxmltree::ParsingOptions::default()
+    };
+    let spec = roxmltree::Document::parse_with_options(&spec_xml, options).expect("Cannot parse AMQP spec");
+    let ext = roxmltree::Document::parse(&ext_xml).expect("Cannot parse RabbitMQ extensions");
+    let roots = [spec.root_element(), ext.root_element()];
+
+    let constants = parse_constants(&roots[0]);
+    let methods = parse_methods(&roots);
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    fs::write(
+        Path::new(&out_dir).join("frame_methods.rs"),
+        generate_frame(&constants, &methods),
+    )
+    .unwrap();
+    fs::write(Path::new(&out_dir).join("codec_methods.rs"), generate_codec(&methods)).unwrap();
+}
+
+fn parse_constants(root: &roxmltree::Node) -> Vec<Constant> {
+    root.children()
+        .filter(|n| n.has_tag_name("constant"))
+        .map(|n| {
+            let name = n.attribute("name").unwrap().to_string();
+
+            Constant {
+                value: n.attribute("value").unwrap().parse().unwrap(),
+                reply_code: n.attribute("class").is_some() || name.starts_with("reply-"),
+                class: n.attribute("class").map(String::from),
+                name,
+            }
+        })
+        .collect()
+}
+
+/// Parses the methods of the spec and applies the extensions on them.
+fn parse_methods(roots: &[roxmltree::Node]) -> Vec<Method> {
+    let domain_type = |domain: &str| -> String {
+        roots
+            .iter()
+            .flat_map(|root| root.children())
+            .find(|n| n.has_tag_name("domain") && n.attribute("name") == Some(domain))
+            .and_then(|n| n.attribute("type"))
+            .unwrap_or_else(|| panic!("Unknown domain {}", domain))
+            .to_string()
+    };
+
+    let mut methods: Vec<Method> = vec![];
+
+    for class in roots
+        .iter()
+        .flat_map(|root| root.children())
+        .filter(|n| n.has_tag_name("class"))
+    {
+        let class_name = class.attribute("name").unwrap();
+        let class_index: u32 = class.attribute("index").unwrap().parse().unwrap();
+
+        for method in class.children().filter(|n| n.has_tag_name("method")) {
+            let method_index: u32 = method.attribute("index").unwrap().parse().unwrap();
+            let mut fields = vec![];
+            let mut next_bit = None;
+
+            for field in method.children().filter(|n| n.has_tag_name("field")) {
+                let base_type = match field.attribute("type") {
+                    Some(t) => t.to_string(),
+                    None => domain_type(field.attribute("domain").unwrap()),
+                };
+
+                let bit = if base_type == "bit" {
+                    let bit = next_bit.unwrap_or(0);
+                    assert!(
+                        bit < 8,
+                        "More than 8 bits in {}.{}",
+                        class_name,
+                        method.attribute("name").unwrap()
+                    );
+                    next_bit = Some(bit + 1);
+                    Some(bit)
+                } else {
+                    next_bit = None;
+                    None
+                };
+
+                fields.push(Field {
+                    name: field.attribute("name").unwrap().to_string(),
+                    base_type,
+                    reserved: field.attribute("reserved") == Some("1"),
+                    bit,
+                });
+            }
+
+            let method = Method {
+                class_name: class_name.to_string(),
+                name: method.attribute("name").unwrap().to_string(),
+                class_method: (class_index << 16) | method_index,
+                fields,
+            };
+
+            let bit_runs = method.fields.iter().filter(|f| f.bit == Some(0)).count();
+            assert!(
+                bit_runs <= 1,
+                "Bit fields of {} are not consecutive",
+                method.spec_name()
+            );
+
+            match methods
+                .iter_mut()
+                .find(|m| m.class_name == method.class_name && m.name == method.name)
+            {
+                Some(m) => *m = method,
+                None => methods.push(method),
+            }
+        }
+    }
+
+    methods
+}
+
+fn generate_frame(constants: &[Constant], methods: &[Method]) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "// Generated by build.rs from {}, do not edit.\n", SPEC).unwrap();
+
+    for c in constants {
+        let ty = if c.reply_code {
+            "u16"
+        } else if c.value > 255 {
+            "u32"
+        } else {
+            "u8"
+        };
+
+        writeln!(out, "pub const {}: {} = {};", upper_snake(&c.name), ty, c.value).unwrap();
+    }
+
+    generate_reply_code(&mut out, constants);
+
+    writeln!(out).unwrap();
+
+    let mut classes: Vec<(&str, u32)> = vec![];
+
+    for m in methods {
+        if !classes.iter().any(|(name, _)| *name == m.class_name) {
+            classes.push((&m.class_name, m.class_method >> 16));
+        }
+    }
+
+    for (name, index) in classes {
+        writeln!(out, "pub const {}_CLASS: u16 = {};", upper_snake(name), index).unwrap();
+    }
+
+    writeln!(out).unwrap();
+
+    for m in methods {
+        writeln!(
+            out,
+            "pub const {}: ClassMethod = 0x{:08X};",
+            m.const_name(),
+            m.class_method
+        )
+        .unwrap();
+    }
+
+    for m in methods {
+        if m.has_flags() {
+            writeln!(out, "\nbitflags! {{").unwrap();
+            writeln!(out, "    /// Bit fields of `{}`.", m.spec_name()).unwrap();
+            writeln!(out, "    #[derive(Default)]").unwrap();
+            writeln!(out, "    {}", SERDE_DERIVE).unwrap();
+            writeln!(out, "    #[cfg_attr(feature = \"serde\", serde(transparent))]").unwrap();
+            writeln!(out, "    pub struct {}Flags: u8 {{", m.type_name()).unwrap();
+
+            for f in m.bits() {
+                writeln!(
+                    out,
+                    "        const {} = 0b{:08b};",
+                    upper_snake(&f.name),
+                    1u8 << f.bit.unwrap()
+                )
+                .unwrap();
+            }
+
+            writeln!(out, "    }}\n}}").unwrap();
+        }
+
+        if !m.has_args() {
+            continue;
+        }
+
+        writeln!(out, "\n/// Arguments of `{}`.", m.spec_name()).unwrap();
+        writeln!(out, "#[derive(Clone, Debug, Default)]").unwrap();
+        writeln!(out, "{}", SERDE_DERIVE).unwrap();
+        writeln!(out, "pub struct {}Args {{", m.type_name()).unwrap();
+
+        let mut flags_written = false;
+
+        for f in m.args() {
+            if f.bit.is_some() && m.has_flags() {
+                if !flags_written {
+                    writeln!(out, "    pub flags: {}Flags,", m.type_name()).unwrap();
+                    flags_written = true;
+                }
+                continue;
+            }
+
+            writeln!(out, "    pub {}: {},", m.field_name(f), rust_type(&f.base_type)).unwrap();
+        }
+
+        writeln!(out, "}}").unwrap();
+    }
+
+    writeln!(out, "\n/// Represents all types of method frame arguments.").unwrap();
+    writeln!(out, "#[derive(Clone, Debug)]").unwrap();
+    writeln!(out, "{}", SERDE_DERIVE).unwrap();
+    writeln!(out, "pub enum MethodFrameArgs {{").unwrap();
+
+    for m in methods {
+        if m.has_args() {
+            writeln!(out, "    {}({}Args),", m.type_name(), m.type_name()).unwrap();
+        } else {
+            writeln!(out, "    {},", m.type_name()).unwrap();
+        }
+    }
+
+    writeln!(out, "}}").unwrap();
+
+    writeln!(out, "\nimpl MethodFrameArgs {{").unwrap();
+    writeln!(out, "    /// The class id and method id of the method in one number.").unwrap();
+    writeln!(out, "    pub fn class_method(&self) -> ClassMethod {{").unwrap();
+    writeln!(out, "        match self {{").unwrap();
+
+    for m in methods {
+        let pattern = if m.has_args() { "(_)" } else { "" };
+
+        writeln!(
+            out,
+            "            MethodFrameArgs::{}{} => {},",
+            m.type_name(),
+            pattern,
+            m.const_name()
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "        }}\n    }}\n}}").unwrap();
+
+    writeln!(
+        out,
+        "\n/// Name of the method as it is in the spec, like `basic.publish`."
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "pub fn method_name(class_method: ClassMethod) -> Option<&'static str> {{"
+    )
+    .unwrap();
+    writeln!(out, "    match class_method {{").unwrap();
+
+    for m in methods {
+        writeln!(out, "        {} => Some(\"{}\"),", m.const_name(), m.spec_name()).unwrap();
+    }
+
+    writeln!(out, "        _ => None,\n    }}\n}}").unwrap();
+
+    out
+}
+
+fn generate_reply_code(out: &mut String, constants: &[Constant]) {
+    let codes = constants.iter().filter(|c| c.reply_code).collect::<Vec<_>>();
+    let variants_of = |class: &str| -> String {
+        codes
+            .iter()
+            .filter(|c| c.class.as_deref() == Some(class))
+            .map(|c| format!("ReplyCode::{}", camel(&c.name)))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    };
+
+    writeln!(out, "\n/// Reply codes of the connection and channel close methods.").unwrap();
+    writeln!(out, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]").unwrap();
+    writeln!(out, "{}", SERDE_DERIVE).unwrap();
+    writeln!(out, "#[repr(u16)]").unwrap();
+    writeln!(out, "pub enum ReplyCode {{").unwrap();
+
+    for c in &codes {
+        writeln!(out, "    {} = {},", camel(&c.name), upper_snake(&c.name)).unwrap();
+    }
+
+    writeln!(out, "}}\n\nimpl ReplyCode {{").unwrap();
+    writeln!(
+        out,
+        "    /// The reply code of the numeric code, `None` if it is not in the spec."
+    )
+    .unwrap();
+    writeln!(out, "    pub fn from_code(code: u16) -> Option<ReplyCode> {{").unwrap();
+    writeln!(out, "        match code {{").unwrap();
+
+    for c in &codes {
+        writeln!(
+            out,
+            "            {} => Some(ReplyCode::{}),",
+            upper_snake(&c.name),
+            camel(&c.name)
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "            _ => None,\n        }}\n    }}\n").unwrap();
+    writeln!(
+        out,
+        "    /// Soft errors close the channel, the connection remains open."
+    )
+    .unwrap();
+    writeln!(out, "    pub fn is_soft_error(self) -> bool {{").unwrap();
+    writeln!(out, "        matches!(self, {})\n    }}\n", variants_of("soft-error")).unwrap();
+    writeln!(out, "    /// Hard errors close the connection.").unwrap();
+    writeln!(out, "    pub fn is_hard_error(self) -> bool {{").unwrap();
+    writeln!(out, "        matches!(self, {})\n    }}\n}}", variants_of("hard-error")).unwrap();
+
+    writeln!(out, "\nimpl From<ReplyCode> for u16 {{").unwrap();
+    writeln!(
+        out,
+        "    fn from(code: ReplyCode) -> u16 {{\n        code as u16\n    }}\n}}"
+    )
+    .unwrap();
+}
+
+fn generate_codec(methods: &[Method]) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "// Generated by build.rs from {}, do not edit.\n", SPEC).unwrap();
+
+    writeln!(
+        out,
+        "fn decode_method_frame_args(src: &mut BytesMut, class_method: ClassMethod) -> Result<MethodFrameArgs> {{"
+    )
+    .unwrap();
+    writeln!(out, "    match class_method {{").unwrap();
+
+    for m in methods {
+        writeln!(out, "        {} => {{", m.const_name()).unwrap();
+
+        for f in &m.fields {
+            if let Some(bit) = f.bit {
+                if bit == 0 {
+                    if bit_run(m, f).any(|g| !g.reserved) {
+                        writeln!(out, "            let bits = decode_u8(src)?;").unwrap();
+                    } else {
+                        writeln!(out, "            let _ = decode_u8(src)?;").unwrap();
+                    }
+                }
+
+                if f.reserved || m.has_flags() {
+                    continue;
+                }
+
+                writeln!(
+                    out,
+                    "            let {} = bits & 0b{:08b} != 0;",
+                    m.field_name(f),
+                    1u8 << bit
+                )
+                .unwrap();
+            } else if f.reserved {
+                writeln!(out, "            let _ = {}(src)?;", decode_fn(&f.base_type)).unwrap();
+            } else {
+                writeln!(
+                    out,
+                    "            let {} = {}(src)?;",
+                    m.field_name(f),
+                    decode_fn(&f.base_type)
+                )
+                .unwrap();
+            }
+        }
+
+        if m.has_args() {
+            let mut names = vec![];
+
+            for f in m.args() {
+                if f.bit.is_some() && m.has_flags() {
+                    if !names.contains(&"flags".to_string()) {
+                        names.push("flags".to_string());
+                    }
+                } else {
+                    names.push(m.field_name(f));
+                }
+            }
+
+            if m.has_flags() {
+                writeln!(
+                    out,
+                    "            let flags = {}Flags::from_bits_truncate(bits);",
+                    m.type_name()
+                )
+                .unwrap();
+            }
+
+            writeln!(
+                out,
+                "\n            Ok(MethodFrameArgs::{}({}Args {{ {} }}))",
+                m.type_name(),
+                m.type_name(),
+                names.join(", ")
+            )
+            .unwrap();
+        } else {
+            if !m.fields.is_empty() {
+                writeln!(out).unwrap();
+            }
+
+            writeln!(out, "            Ok(MethodFrameArgs::{})", m.type_name()).unwrap();
+        }
+
+        writeln!(out, "        }}").unwrap();
+    }
+
+    writeln!(
+        out,
+        "        _ => frame_error!(NOT_IMPLEMENTED, format!(\"Unknown method frame {{:08X}}\", class_method)),"
+    )
+    .unwrap();
+    writeln!(out, "    }}\n}}\n").unwrap();
+
+    writeln!(
+        out,
+        "fn encode_method_frame_args(buf: &mut BytesMut, args: &MethodFrameArgs) -> Result<()> {{"
+    )
+    .unwrap();
+    writeln!(out, "    match args {{").unwrap();
+
+    for m in methods {
+        if m.fields.is_empty() {
+            writeln!(out, "        MethodFrameArgs::{} => (),", m.type_name()).unwrap();
+            continue;
+        }
+
+        if m.has_args() {
+            writeln!(out, "        MethodFrameArgs::{}(args) => {{", m.type_name()).unwrap();
+        } else {
+            writeln!(out, "        MethodFrameArgs::{} => {{", m.type_name()).unwrap();
+        }
+
+        for f in &m.fields {
+            if let Some(bit) = f.bit {
+                if bit != 0 {
+                    continue;
+                }
+
+                // the whole bit run is written when its first bit is reached
+                let mut parts = vec![];
+
+                for g in bit_run(m, f).filter(|g| !g.reserved) {
+                    if m.has_flags() {
+                        parts = vec!["args.flags.bits()".to_string()];
+                        break;
+                    }
+
+                    let shift = g.bit.unwrap();
+
+                    if shift == 0 {
+                        parts.push(format!("args.{} as u8", m.field_name(g)));
+                    } else {
+                        parts.push(format!("(args.{} as u8) << {}", m.field_name(g), shift));
+                    }
+                }
+
+                if parts.is_empty() {
+                    parts.push("0".to_string());
+                }
+
+                writeln!(out, "            buf.put_u8({});", parts.join(" | ")).unwrap();
+            } else if f.reserved {
+                writeln!(out, "            {};", encode_zero(&f.base_type)).unwrap();
+            } else {
+                writeln!(
+                    out,
+                    "            {};",
+                    encode_field(&f.base_type, &format!("args.{}", m.field_name(f)))
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(out, "        }}").unwrap();
+    }
+
+    writeln!(out, "    }}\n\n    Ok(())\n}}").unwrap();
+
+    out
+}
+
+/// The consecutive bit fields of the method starting with `first`.
+fn bit_run<'a>(method: &'a Method, first: &'a Field) -> impl Iterator<Item = &'a Field> {
+    method
+        .fields
+        .iter()
+        .skip_while(move |f| !std::ptr::eq(*f, first))
+        .take_while(|f| f.bit.is_some())
+}
+
+fn rust_type(base_type: &str) -> &'static str {
+    match base_type {
+        "bit" => "bool",
+        "octet" => "u8",
+        "short" => "u16",
+        "long" => "u32",
+        "longlong" | "timestamp" => "u64",
+        "shortstr" => "String",
+        // long strings are binary, like the SASL responses
+        "longstr" => "Bytes",
+        "table" => "Option<FieldTable>",
+        t => panic!("Unknown type {}", t),
+    }
+}
+
+fn decode_fn(base_type: &str) -> &'static str {
+    match base_type {
+        "octet" => "decode_u8",
+        "short" => "decode_u16",
+        "long" => "decode_u32",
+        "longlong" | "timestamp" => "decode_u64",
+        "shortstr" => "decode_short_string",
+        "longstr" => "decode_long_string",
+        "table" => "decode_field_table",
+        t => panic!("Unknown type {}", t),
+    }
+}
+
+fn encode_field(base_type: &str, value: &str) -> String {
+    match base_type {
+        "octet" => format!("buf.put_u8({})", value),
+        "short" => format!("buf.put_u16({})", value),
+        "long" => format!("buf.put_u32({})", value),
+        "longlong" | "timestamp" => format!("buf.put_u64({})", value),
+        "shortstr" => format!("encode_short_string(buf, &{})?", value),
+        "longstr" => format!("encode_long_string(buf, &{})?", value),
+        "table" => format!("encode_field_table(buf, {}.as_ref())?", value),
+        t => panic!("Unknown type {}", t),
+    }
+}
+
+fn encode_zero(base_type: &str) -> String {
+    match base_type {
+        "shortstr" => "encode_short_string(buf, \"\")?".to_string(),
+        "longstr" => "encode_long_string(buf, b\"\")?".to_string(),
+        "table" => "encode_field_table(buf, None)?".to_string(),
+        t => encode_field(t, "0"),
+    }
+}
+
+fn snake(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+fn upper_snake(name: &str) -> String {
+    snake(name).to_uppercase()
+}
+
+fn camel(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,99 @@
+//! Print the frames of a captured AMQP byte stream.
+//!
+//! ```text
+//! amqp-dump [--hex] [FILE]
+//! ```
+//!
+//! The capture is one direction of a connection, read from `FILE` or from the standard input. With
+//! `--hex` the input is hex text (like `xxd -p` or Wireshark raw hex output), otherwise raw bytes.
+use bytes::BytesMut;
+use ironmq_codec::codec::AMQPCodec;
+use ironmq_codec::dump::{format_frame, hex_dump, parse_hex};
+use ironmq_codec::ProtocolHeaderError;
+use std::io::{self, Read};
+use std::{env, fs, process};
+use tokio_util::codec::Decoder;
+
+fn main() {
+    let mut hex = false;
+    let mut path = None;
+
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--hex" => hex = true,
+            "-h" | "--help" => usage(0),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(1),
+        }
+    }
+
+    let input = match read_input(path.as_deref()) {
+        Ok(input) => input,
+        Err(e) => fail(&format!("Cannot read input: {}", e)),
+    };
+
+    let bytes = if hex {
+        match parse_hex(&String::from_utf8_lossy(&input)) {
+            Ok(bytes) => bytes,
+            Err(e) => fail(&e),
+        }
+    } else {
+        input
+    };
+
+    dump(&bytes);
+}
+
+fn dump(bytes: &[u8]) {
+    // no frame size limit, we want to see everything
+    let mut codec = AMQPCodec::new(0);
+    let mut buf = BytesMut::from(bytes);
+
+    loop {
+        let offset = bytes.len() - buf.len();
+
+        match codec.decode(&mut buf) {
+            Ok(Some(frame)) => println!("{:08X}  {}", offset, format_frame(&frame)),
+            Ok(None) => {
+                if !buf.is_empty() {
+                    println!("{:08X}  incomplete frame, {} bytes left", offset, buf.len());
+                    print!("{}", hex_dump(&buf));
+                }
+
+                return;
+            }
+            // the header is consumed, so we can go on with the frames
+            Err(e) if e.is::<ProtocolHeaderError>() => println!("{:08X}  {}", offset, e),
+            Err(e) => {
+                println!("{:08X}  cannot decode frame: {}", offset, e);
+                print!("{}", hex_dump(&bytes[offset..]));
+
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn read_input(path: Option<&str>) -> io::Result<Vec<u8>> {
+    match path {
+        None | Some("-") => {
+            let mut input = vec![];
+            io::stdin().read_to_end(&mut input)?;
+
+            Ok(input)
+        }
+        Some(path) => fs::read(path),
+    }
+}
+
+fn usage(code: i32) -> ! {
+    eprintln!("Usage: amqp-dump [--hex] [FILE]");
+
+    process::exit(code);
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+
+    process::exit(1);
+}
@@ -1,400 +1,497 @@
 use crate::frame::*;
-use bytes::{Buf, BufMut, BytesMut};
+use crate::{frame_error, ProtocolHeaderError, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use tokio_util::codec::{Decoder, Encoder};
 
-const FRAME_METHOD_FRAME: u8 = 0x01;
-const FRAME_CONTENT_HEADER: u8 = 0x02;
-const FRAME_CONTENT_BODY: u8 = 0x03;
-const FRAME_HEARTBEAT: u8 = 0x08;
 const FRAME_AMQP_VERSION: u8 = 0x41;
 
-/// Placeholder for AMQP encoder and decoder functions.
-pub struct AMQPCodec {}
+/// Frame type (1), channel (2) and frame size (4).
+const FRAME_HEADER_SIZE: usize = 7;
 
-// TODO change type of encoder, decoder, they should deal with Vec<AMQPFrame>
+/// Protocol header of AMQP 0-9-1, the only version we speak.
+pub const PROTOCOL_HEADER: &[u8; 8] = b"AMQP\x00\x00\x09\x01";
 
-impl Encoder<AMQPFrame> for AMQPCodec {
-    type Error = std::io::Error;
+/// Frame size which is advertised by the server and the client during the connection tuning.
+pub const DEFAULT_FRAME_MAX: u32 = 131_072;
 
-    fn encode(&mut self, event: AMQPFrame, mut buf: &mut BytesMut) -> Result<(), Self::Error> {
-        match event {
-            AMQPFrame::Header => buf.put(&b"AMQP\x00\x00\x09\x01"[..]),
+/// How deep arrays and field tables can be nested in a field value, so a malicious frame cannot
+/// overflow the stack of the decoder.
+const MAX_NESTING_DEPTH: usize = 64;
 
-            AMQPFrame::Method(ch, cm, args) => encode_method_frame(&mut buf, ch, cm, &args),
+/// AMQP encoder and decoder.
+///
+/// The codec refuses to decode and encode frames which are bigger than `frame_max`. Before the
+/// connection tuning it is the size the peer advertised, after the tuning it should be set to the
+/// negotiated value by `set_frame_max`. Zero means that there is no limit.
+///
+/// The encoder also checks that the content body frames add up to the body size of their content
+/// header, so a wrong content doesn't reach the peer.
+#[derive(Debug, Default)]
+pub struct AMQPCodec {
+    frame_max: usize,
+    /// Body bytes which are still to be encoded per channel.
+    outgoing_contents: HashMap<Channel, u64>,
+}
 
-            AMQPFrame::ContentHeader(header_frame) => {
-                encode_content_header_frame(&mut buf, &header_frame)
-            }
+impl AMQPCodec {
+    pub fn new(frame_max: u32) -> Self {
+        AMQPCodec {
+            frame_max: frame_max as usize,
+            outgoing_contents: HashMap::new(),
+        }
+    }
 
-            AMQPFrame::ContentBody(body_frame) => encode_content_body_frame(&mut buf, &body_frame),
+    pub fn frame_max(&self) -> u32 {
+        self.frame_max as u32
+    }
 
-            AMQPFrame::Heartbeat(channel) => encode_heartbeat_frame(&mut buf, channel),
-        }
+    /// Set the maximum frame size (including frame header and frame end) after the connection
+    /// tuning.
+    pub fn set_frame_max(&mut self, frame_max: u32) {
+        self.frame_max = frame_max as usize;
+    }
 
-        Ok(())
+    fn is_too_large(&self, frame_size: usize) -> bool {
+        self.frame_max > 0 && frame_size > self.frame_max
     }
-}
 
-impl Decoder for AMQPCodec {
-    type Item = AMQPFrame;
-    type Error = std::io::Error;
+    /// Check the frame against the content being encoded on its channel, and give back the body
+    /// bytes which remain after the frame.
+    fn remaining_content(&self, frame: &AMQPFrame) -> Result<Option<(Channel, u64)>> {
+        match frame {
+            AMQPFrame::Method(channel, _, _) if self.outgoing_contents.contains_key(channel) => {
+                frame_error!(UNEXPECTED_FRAME, "Method frame before the content body is complete")
+            }
+            AMQPFrame::ContentHeader(header) => {
+                if self.outgoing_contents.contains_key(&header.channel) {
+                    return frame_error!(UNEXPECTED_FRAME, "Content header before the previous body is complete");
+                }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if src.len() < 8 {
-            Ok(None)
-        } else {
-            match src.get_u8() {
-                FRAME_METHOD_FRAME => {
-                    let channel = src.get_u16();
-                    // TODO amqp frame can be u32 but Buf handles only usize buffes
-                    let frame_len = src.get_u32() as usize;
+                Ok(Some((header.channel, header.body_size)))
+            }
+            AMQPFrame::ContentBody(body) => match self.outgoing_contents.get(&body.channel) {
+                Some(&remaining) if body.body.len() as u64 <= remaining => {
+                    Ok(Some((body.channel, remaining - body.body.len() as u64)))
+                }
+                Some(_) => frame_error!(FRAME_ERROR, "Content body is longer than the body size in the header"),
+                None => frame_error!(UNEXPECTED_FRAME, "Content body without content header"),
+            },
+            _ => Ok(None),
+        }
+    }
+}
 
-                    let mut frame_buf = src.split_to(frame_len);
-                    let frame = decode_method_frame(&mut frame_buf, channel);
+impl Encoder<AMQPFrame> for AMQPCodec {
+    type Error = crate::Error;
 
-                    let _frame_separator = src.get_u8();
+    fn encode(&mut self, event: AMQPFrame, buf: &mut BytesMut) -> Result<()> {
+        let start = buf.len();
+        let remaining = self.remaining_content(&event)?;
 
-                    Ok(Some(frame))
-                }
-                FRAME_CONTENT_HEADER => {
-                    let channel = src.get_u16();
-                    let frame_len = src.get_u32() as usize;
+        let result = match event {
+            AMQPFrame::Header => {
+                buf.put(&PROTOCOL_HEADER[..]);
+                Ok(())
+            }
 
-                    let mut frame_buf = src.split_to(frame_len);
-                    let frame = decode_content_header_frame(&mut frame_buf, channel);
+            AMQPFrame::Method(ch, cm, args) => encode_method_frame(buf, ch, cm, &args),
 
-                    let _frame_separator = src.get_u8();
+            AMQPFrame::ContentHeader(header_frame) => {
+                encode_content_header_frame(buf, &header_frame)
+            }
 
-                    Ok(Some(frame))
-                }
-                FRAME_CONTENT_BODY => {
-                    let channel = src.get_u16();
-                    let body_len = src.get_u32();
-                    let bytes = src.split_to(body_len as usize);
+            AMQPFrame::ContentBody(body_frame) => encode_content_body_frame(buf, &body_frame),
 
-                    let _frame_separator = src.get_u8();
+            AMQPFrame::Heartbeat(channel) => {
+                encode_heartbeat_frame(buf, channel);
+                Ok(())
+            }
+        };
 
-                    // TODO more effective copy
-                    let frame = AMQPFrame::ContentBody(ContentBodyFrame {
-                        channel: channel,
-                        body: bytes.to_vec(),
-                    });
+        if result.is_err() {
+            buf.truncate(start);
 
-                    Ok(Some(frame))
-                }
-                FRAME_HEARTBEAT => {
-                    let channel = src.get_u16();
-                    let len = src.get_u32();
-                    let _ = src.split_to(len as usize);
+            return result;
+        }
 
-                    let _frame_separator = src.get_u8();
+        let frame_size = buf.len() - start;
 
-                    Ok(Some(AMQPFrame::Heartbeat(channel)))
-                }
-                FRAME_AMQP_VERSION => {
-                    let mut head = [0u8; 7];
-                    src.copy_to_slice(&mut head);
+        if self.is_too_large(frame_size) {
+            buf.truncate(start);
 
-                    // TODO check if version is 0091
+            return frame_error!(
+                FRAME_ERROR,
+                format!("Frame size {} exceeds frame max {}", frame_size, self.frame_max)
+            );
+        }
 
-                    Ok(Some(AMQPFrame::Header))
-                }
-                f => Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Unknown frame {}", f),
-                )),
+        match remaining {
+            Some((channel, 0)) => {
+                self.outgoing_contents.remove(&channel);
+            }
+            Some((channel, bytes)) => {
+                self.outgoing_contents.insert(channel, bytes);
             }
+            None => (),
         }
+
+        Ok(())
     }
 }
 
-// TODO have an Error type here, and it should be result<>
-fn decode_method_frame(mut src: &mut BytesMut, channel: u16) -> AMQPFrame {
-    let class_method = src.get_u32();
-
-    let method_frame_args = match class_method {
-        CONNECTION_START => decode_connection_start(&mut src),
-        CONNECTION_START_OK => decode_connection_start_ok(&mut src),
-        CONNECTION_TUNE => decode_connection_tune(&mut src),
-        CONNECTION_TUNE_OK => decode_connection_tune_ok(&mut src),
-        CONNECTION_OPEN => decode_connection_open(&mut src),
-        CONNECTION_OPEN_OK => decode_connection_open_ok(&mut src),
-        CONNECTION_CLOSE => decode_connection_close(&mut src),
-        CONNECTION_CLOSE_OK => MethodFrameArgs::ConnectionCloseOk,
-        CHANNEL_OPEN => decode_channel_open(&mut src),
-        CHANNEL_OPEN_OK => decode_channel_open_ok(&mut src),
-        CHANNEL_CLOSE => decode_channel_close(&mut src),
-        CHANNEL_CLOSE_OK => MethodFrameArgs::ChannelCloseOk,
-        EXCHANGE_DECLARE => decode_exchange_declare(&mut src),
-        EXCHANGE_DECLARE_OK => MethodFrameArgs::ExchangeBindOk,
-        QUEUE_DECLARE => decode_queue_declare(&mut src),
-        QUEUE_DECLARE_OK => decode_queue_declare_ok(&mut src),
-        QUEUE_BIND => decode_queue_bind(&mut src),
-        QUEUE_BIND_OK => MethodFrameArgs::QueueBindOk,
-        BASIC_CONSUME => decode_basic_consume(&mut src),
-        BASIC_CONSUME_OK => decode_basic_consume_ok(&mut src),
-        BASIC_DELIVER => decode_basic_deliver(&mut src),
-        BASIC_PUBLISH => decode_basic_publish(&mut src),
-        _ =>
-            unimplemented!("{:08X}", class_method)
-    };
+/// Encode several frames in one go, so a method frame and its content frames can be written to
+/// the socket with one flush. Every frame is checked against `frame_max` individually, and the
+/// contents started in the batch need to be complete. If any frame is wrong, nothing is encoded.
+impl Encoder<Vec<AMQPFrame>> for AMQPCodec {
+    type Error = crate::Error;
 
-    AMQPFrame::Method(channel, class_method, method_frame_args)
-}
+    fn encode(&mut self, frames: Vec<AMQPFrame>, buf: &mut BytesMut) -> Result<()> {
+        let start = buf.len();
+        let contents = self.outgoing_contents.clone();
+        let mut started = vec![];
 
-fn decode_connection_start(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = ConnectionStartArgs::default();
-    args.version_major = src.get_u8();
-    args.version_minor = src.get_u8();
-    args.properties = decode_field_table(&mut src);
-    args.mechanisms = decode_long_string(&mut src);
-    args.locales = decode_long_string(&mut src);
+        let mut result = Ok(());
 
-    //if let Some(ref table) = args.properties {
-    //    if let Some(AMQPFieldValue::FieldTable(cap)) = table.get("capabilities".into()) {
-    //        args.capabilities = Some(**cap.clone());
-    //    }
-    //}
+        for frame in frames {
+            if let AMQPFrame::ContentHeader(header) = &frame {
+                started.push(header.channel);
+            }
 
-    MethodFrameArgs::ConnectionStart(args)
-}
+            result = self.encode(frame, buf);
+
+            if result.is_err() {
+                break;
+            }
+        }
 
-fn decode_connection_start_ok(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = ConnectionStartOkArgs::default();
-    args.properties = decode_field_table(&mut src);
-    args.mechanism = decode_short_string(&mut src);
-    args.response = decode_long_string(&mut src);
-    args.locale = decode_short_string(&mut src);
+        if result.is_ok() && started.iter().any(|ch| self.outgoing_contents.contains_key(ch)) {
+            result = frame_error!(FRAME_ERROR, "Content body is shorter than the body size in the header");
+        }
 
-    // TODO init capabilities!
+        if result.is_err() {
+            buf.truncate(start);
+            self.outgoing_contents = contents;
+        }
 
-    MethodFrameArgs::ConnectionStartOk(args)
+        result
+    }
 }
 
-fn decode_connection_tune(src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = ConnectionTuneArgs::default();
-    args.channel_max = src.get_u16();
-    args.frame_max = src.get_u32();
-    args.heartbeat = src.get_u16();
+/// The decoder returns `Ok(None)` until a complete frame is buffered. Malformed frames are
+/// reported as `FrameError`s with the AMQP reply code which can be sent back in a connection
+/// close frame.
+impl Decoder for AMQPCodec {
+    type Item = AMQPFrame;
+    type Error = crate::Error;
 
-    MethodFrameArgs::ConnectionTune(args)
-}
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        if src.is_empty() {
+            return Ok(None);
+        }
 
-fn decode_connection_tune_ok(src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = ConnectionTuneOkArgs::default();
-    args.channel_max = src.get_u16();
-    args.frame_max = src.get_u32();
-    args.heartbeat = src.get_u16();
+        if src[0] == FRAME_AMQP_VERSION {
+            if src.len() < 8 {
+                return Ok(None);
+            }
 
-    MethodFrameArgs::ConnectionTuneOk(args)
-}
+            let head = src.split_to(8);
 
-fn decode_connection_open(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let virtual_host = decode_short_string(&mut src);
-    let _reserved = decode_short_string(&mut src);
-    let flags = src.get_u8();
+            if head[..] != PROTOCOL_HEADER[..] {
+                let mut header = [0u8; 8];
+                header.copy_from_slice(&head);
 
-    MethodFrameArgs::ConnectionOpen(ConnectionOpenArgs {
-        virtual_host: virtual_host,
-        insist: flags & 0x01 != 0,
-    })
-}
+                return Err(Box::new(ProtocolHeaderError { header }));
+            }
 
-fn decode_connection_open_ok(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let _ = decode_short_string(&mut src);
+            return Ok(Some(AMQPFrame::Header));
+        }
 
-    MethodFrameArgs::ConnectionOpenOk
-}
+        if src.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
 
-fn decode_connection_close(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = ConnectionCloseArgs::default();
-    args.code = src.get_u16();
-    args.text = decode_short_string(&mut src);
-    args.class_id = src.get_u16();
-    args.method_id = src.get_u16();
+        let frame_type = src[0];
+        let channel = u16::from_be_bytes([src[1], src[2]]);
+        // TODO amqp frame can be u32 but Buf handles only usize buffes
+        let frame_len = u32::from_be_bytes([src[3], src[4], src[5], src[6]]) as usize;
+        let total_len = FRAME_HEADER_SIZE + frame_len + 1;
+
+        if self.is_too_large(total_len) {
+            return frame_error!(
+                FRAME_ERROR,
+                format!("Frame size {} exceeds frame max {}", total_len, self.frame_max)
+            );
+        }
 
-    MethodFrameArgs::ConnectionClose(args)
-}
+        // The length comes from the peer, so the buffer grows with the arriving bytes instead of
+        // reserving the whole frame upfront.
+        if src.len() < total_len {
+            return Ok(None);
+        }
 
-fn decode_channel_open(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let _ = decode_short_string(&mut src);
+        src.advance(FRAME_HEADER_SIZE);
 
-    MethodFrameArgs::ChannelOpen
-}
+        let mut frame_buf = src.split_to(frame_len);
 
-fn decode_channel_open_ok(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let _ = decode_long_string(&mut src);
+        if src.get_u8() != FRAME_END {
+            return frame_error!(FRAME_ERROR, "Frame end octet is missing");
+        }
 
-    MethodFrameArgs::ChannelOpenOk
+        let frame = match frame_type {
+            FRAME_METHOD => decode_method_frame(&mut frame_buf, channel)?,
+            FRAME_HEADER => decode_content_header_frame(&mut frame_buf, channel)?,
+            FRAME_BODY => AMQPFrame::ContentBody(ContentBodyFrame {
+                channel,
+                body: frame_buf.freeze(),
+            }),
+            FRAME_HEARTBEAT => AMQPFrame::Heartbeat(channel),
+            f => return frame_error!(FRAME_ERROR, format!("Unknown frame type {}", f)),
+        };
+
+        Ok(Some(frame))
+    }
 }
 
-fn decode_channel_close(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = ChannelCloseArgs::default();
-    args.code = src.get_u16();
-    args.text = decode_short_string(&mut src);
-    args.class_id = src.get_u16();
-    args.method_id = src.get_u16();
+fn decode_method_frame(src: &mut BytesMut, channel: u16) -> Result<AMQPFrame> {
+    let class_method = decode_u32(src)?;
+    let method_frame_args = decode_method_frame_args(src, class_method)?;
 
-    MethodFrameArgs::ChannelClose(args)
+    Ok(AMQPFrame::Method(channel, class_method, method_frame_args))
 }
 
-fn decode_exchange_declare(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = ExchangeDeclareArgs::default();
-    let _ = src.get_u16();
-    args.exchange_name = decode_short_string(&mut src);
-    args.exchange_type = decode_short_string(&mut src);
-    args.flags = ExchangeDeclareFlags::from_bits(src.get_u8()).unwrap_or_default();
-    args.args = decode_field_table(&mut src);
+fn decode_content_header_frame(src: &mut BytesMut, channel: u16) -> Result<AMQPFrame> {
+    let class_id = decode_u16(src)?;
+    let weight = decode_u16(src)?;
+    let body_size = decode_u64(src)?;
+    let property_flags = HeaderPropertyFlags::from_bits_truncate(decode_u16(src)?);
 
-    MethodFrameArgs::ExchangeDeclare(args)
-}
+    let mut flags = property_flags;
+    while flags.contains(HeaderPropertyFlags::CONTINUATION) {
+        // basic class has less than 15 properties, so we don't expect more flags
+        flags = HeaderPropertyFlags::from_bits_truncate(decode_u16(src)?);
+    }
 
-fn decode_queue_declare(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = QueueDeclareArgs::default();
-    let _ = src.get_u16();
-    args.name = decode_short_string(&mut src);
-    args.flags = QueueDeclareFlags::from_bits(src.get_u8()).unwrap_or_default();
-    args.args = decode_field_table(&mut src);
+    let properties = decode_basic_properties(src, property_flags)?;
 
-    MethodFrameArgs::QueueDeclare(args)
+    Ok(AMQPFrame::ContentHeader(ContentHeaderFrame {
+        channel,
+        class_id,
+        weight,
+        body_size,
+        properties,
+    }))
 }
 
-fn decode_queue_declare_ok(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = QueueDeclareOkArgs::default();
-    args.name = decode_short_string(&mut src);
-    args.message_count = src.get_u32();
-    args.consumer_count = src.get_u32();
+/// Decode the property list in the order of the property flags.
+fn decode_basic_properties(src: &mut BytesMut, flags: HeaderPropertyFlags) -> Result<BasicProperties> {
+    let mut props = BasicProperties::default();
 
-    MethodFrameArgs::QueueDeclareOk(args)
+    if flags.contains(HeaderPropertyFlags::CONTENT_TYPE) {
+        props.content_type = Some(decode_short_string(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::CONTENT_ENCODING) {
+        props.content_encoding = Some(decode_short_string(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::HEADERS) {
+        props.headers = Some(decode_field_table(src)?.unwrap_or_default());
+    }
+    if flags.contains(HeaderPropertyFlags::DELIVERY_MODE) {
+        props.delivery_mode = Some(decode_u8(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::PRIORITY) {
+        props.priority = Some(decode_u8(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::CORRELATION_ID) {
+        props.correlation_id = Some(decode_short_string(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::REPLY_TO) {
+        props.reply_to = Some(decode_short_string(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::EXPIRATION) {
+        props.expiration = Some(decode_short_string(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::MESSAGE_ID) {
+        props.message_id = Some(decode_short_string(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::TIMESTAMP) {
+        props.timestamp = Some(decode_u64(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::MESSAGE_TYPE) {
+        props.message_type = Some(decode_short_string(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::USER_ID) {
+        props.user_id = Some(decode_short_string(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::APP_ID) {
+        props.app_id = Some(decode_short_string(src)?);
+    }
+    if flags.contains(HeaderPropertyFlags::CLUSTER_ID) {
+        // cluster id is deprecated, we just skip it
+        let _ = decode_short_string(src)?;
+    }
+
+    Ok(props)
 }
 
-fn decode_queue_bind(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = QueueBindArgs::default();
-    let _ = src.get_u16();
-    args.queue_name = decode_short_string(&mut src);
-    args.exchange_name = decode_short_string(&mut src);
-    args.routing_key = decode_short_string(&mut src);
+fn decode_value(buf: &mut BytesMut, depth: usize) -> Result<AMQPFieldValue> {
+    if depth > MAX_NESTING_DEPTH {
+        return frame_error!(SYNTAX_ERROR, "Field values are nested too deep");
+    }
 
-    args.no_wait = src.get_u8() != 0;
-    args.args = decode_field_table(&mut src);
+    let value = match decode_u8(buf)? {
+        b't' => AMQPFieldValue::Bool(decode_u8(buf)? != 0),
+        b'b' => AMQPFieldValue::I8(decode_u8(buf)? as i8),
+        b'B' => AMQPFieldValue::U8(decode_u8(buf)?),
+        // 'U' is the signed short of the original 0-9-1 spec, 's' is used by RabbitMQ
+        b's' | b'U' => AMQPFieldValue::I16(decode_u16(buf)? as i16),
+        b'u' => AMQPFieldValue::U16(decode_u16(buf)?),
+        b'I' => AMQPFieldValue::I32(decode_u32(buf)? as i32),
+        b'i' => AMQPFieldValue::U32(decode_u32(buf)?),
+        // 'L' is the signed long long of the original 0-9-1 spec, 'l' is used by RabbitMQ
+        b'l' | b'L' => AMQPFieldValue::I64(decode_u64(buf)? as i64),
+        b'f' => AMQPFieldValue::F32(f32::from_bits(decode_u32(buf)?)),
+        b'd' => AMQPFieldValue::F64(f64::from_bits(decode_u64(buf)?)),
+        b'D' => {
+            let scale = decode_u8(buf)?;
+            let value = decode_u32(buf)?;
+
+            AMQPFieldValue::Decimal(scale, value)
+        }
+        b'S' => AMQPFieldValue::LongString(to_string(&decode_long_string(buf)?)?),
+        b'x' => {
+            let len = decode_u32(buf)? as usize;
 
-    MethodFrameArgs::QueueBind(args)
+            AMQPFieldValue::ByteArray(split_bytes(buf, len)?.to_vec())
+        }
+        b'A' => {
+            let len = decode_u32(buf)? as usize;
+            let mut array_buf = split_bytes(buf, len)?;
+            let mut values = vec![];
+
+            while array_buf.has_remaining() {
+                values.push(decode_value(&mut array_buf, depth + 1)?);
+            }
+
+            AMQPFieldValue::Array(values)
+        }
+        b'T' => AMQPFieldValue::Timestamp(decode_u64(buf)?),
+        b'F' => match decode_nested_field_table(buf, depth + 1)? {
+            None => AMQPFieldValue::EmptyFieldTable,
+            Some(table) => AMQPFieldValue::FieldTable(Box::new(table)),
+        },
+        b'V' => AMQPFieldValue::Void,
+        t => return frame_error!(SYNTAX_ERROR, format!("Unknown field value type {}", t)),
+    };
+
+    Ok(value)
+}
+
+/// Check if the buffer has at least `len` bytes, so it can be read without panic.
+fn ensure_remaining(buf: &BytesMut, len: usize) -> Result<()> {
+    if buf.remaining() < len {
+        frame_error!(SYNTAX_ERROR, "Frame is shorter than its arguments")
+    } else {
+        Ok(())
+    }
 }
 
-fn decode_basic_consume(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = BasicConsumeArgs::default();
-    let _ = src.get_u16();
-    args.queue = decode_short_string(&mut src);
-    args.consumer_tag = decode_short_string(&mut src);
-    args.flags = BasicConsumeFlags::from_bits(src.get_u8()).unwrap_or_default();
-    args.args = decode_field_table(&mut src);
+fn split_bytes(buf: &mut BytesMut, len: usize) -> Result<BytesMut> {
+    ensure_remaining(buf, len)?;
 
-    MethodFrameArgs::BasicConsume(args)
+    Ok(buf.split_to(len))
 }
 
-fn decode_basic_consume_ok(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = BasicConsumeOkArgs::default();
-    args.consumer_tag = decode_short_string(&mut src);
+fn decode_u8(buf: &mut BytesMut) -> Result<u8> {
+    ensure_remaining(buf, 1)?;
 
-    MethodFrameArgs::BasicConsumeOk(args)
+    Ok(buf.get_u8())
 }
 
-fn decode_basic_deliver(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = BasicDeliverArgs::default();
-    args.consumer_tag = decode_short_string(&mut src);
-    args.delivery_tag = src.get_u64();
-    args.redelivered = src.get_u8() != 0;
-    args.exchange_name = decode_short_string(&mut src);
-    args.routing_key = decode_short_string(&mut src);
+fn decode_u16(buf: &mut BytesMut) -> Result<u16> {
+    ensure_remaining(buf, 2)?;
 
-    MethodFrameArgs::BasicDeliver(args)
+    Ok(buf.get_u16())
 }
 
-fn decode_basic_publish(mut src: &mut BytesMut) -> MethodFrameArgs {
-    let mut args = BasicPublishArgs::default();
-    let _ = src.get_u16();
-    args.exchange_name = decode_short_string(&mut src);
-    args.routing_key = decode_short_string(&mut src);
-    args.flags = BasicPublishFlags::from_bits(src.get_u8()).unwrap_or_default();
+fn decode_u32(buf: &mut BytesMut) -> Result<u32> {
+    ensure_remaining(buf, 4)?;
 
-    MethodFrameArgs::BasicPublish(args)
+    Ok(buf.get_u32())
 }
 
+fn decode_u64(buf: &mut BytesMut) -> Result<u64> {
+    ensure_remaining(buf, 8)?;
 
-fn decode_content_header_frame(src: &mut BytesMut, channel: u16) -> AMQPFrame {
-    let class_id = src.get_u16();
-    let weight = src.get_u16();
-    let body_size = src.get_u64();
-    let property_flags = src.get_u16();
-    // TODO property list, it seems that we need to know from the class_id what is the type list
+    Ok(buf.get_u64())
+}
+
+fn decode_short_string(buf: &mut BytesMut) -> Result<String> {
+    let len = decode_u8(buf)? as usize;
+    let sb = split_bytes(buf, len)?;
 
-    AMQPFrame::ContentHeader(ContentHeaderFrame {
-        channel: channel,
-        class_id: class_id,
-        weight: weight,
-        body_size: body_size,
-        prop_flags: property_flags,
-        args: vec![],
-    })
+    to_string(&sb)
 }
 
-fn decode_value(mut buf: &mut BytesMut) -> AMQPFieldValue {
-    match buf.get_u8() {
-        b't' => {
-            let bool_value = buf.get_u8() != 0;
+/// Long strings are raw bytes, the field values which need to be text are converted by
+/// `to_string`.
+fn decode_long_string(buf: &mut BytesMut) -> Result<Bytes> {
+    let len = decode_u32(buf)? as usize;
 
-            AMQPFieldValue::Bool(bool_value)
-        }
-        b'S' => {
-            let string_value = decode_long_string(&mut buf);
+    Ok(split_bytes(buf, len)?.freeze())
+}
 
-            AMQPFieldValue::LongString(string_value)
-        }
-        b'F' => match decode_field_table(&mut buf) {
-            None => AMQPFieldValue::EmptyFieldTable,
-            Some(table) => AMQPFieldValue::FieldTable(Box::new(table)),
-        },
-        t => panic!("Unknown type {}", t),
+fn to_string(bytes: &[u8]) -> Result<String> {
+    match String::from_utf8(bytes.to_vec()) {
+        Ok(s) => Ok(s),
+        Err(_) => frame_error!(SYNTAX_ERROR, "String is not valid UTF-8"),
     }
 }
 
-fn decode_short_string(buf: &mut BytesMut) -> String {
-    let len = buf.get_u8() as usize;
-    let sb = buf.split_to(len);
+/// Decode the fields of a table which doesn't have the length prefix, like the response of the
+/// AMQPLAIN mechanism in `connection.start-ok`.
+pub fn decode_table_fields(bytes: &[u8]) -> Result<FieldTable> {
+    let mut buf = BytesMut::from(bytes);
+    let mut fields = vec![];
 
-    String::from_utf8(sb.to_vec()).unwrap()
-}
+    while buf.has_remaining() {
+        let field_name = decode_short_string(&mut buf)?;
+        let field_value = decode_value(&mut buf, 0)?;
 
-fn decode_long_string(buf: &mut BytesMut) -> String {
-    let len = buf.get_u32() as usize;
-    let sb = buf.split_to(len);
+        fields.push((field_name, field_value));
+    }
 
-    String::from_utf8(sb.to_vec()).unwrap()
+    Ok(fields.into_iter().collect())
 }
 
 /// Decode a field table
 ///
 /// The buffer points to the beginning of the field table which is a `u32` length
 /// information.
-fn decode_field_table(buf: &mut BytesMut) -> Option<HashMap<String, AMQPFieldValue>> {
-    let ft_len = buf.get_u32() as usize;
+fn decode_field_table(buf: &mut BytesMut) -> Result<Option<FieldTable>> {
+    decode_nested_field_table(buf, 0)
+}
+
+/// Decode a field table which is the value of a field `depth` levels deep.
+fn decode_nested_field_table(buf: &mut BytesMut, depth: usize) -> Result<Option<FieldTable>> {
+    let ft_len = decode_u32(buf)? as usize;
 
     if ft_len == 0 {
-        return None;
+        return Ok(None);
     }
 
-    let mut ft_buf = buf.split_to(ft_len);
-    let mut table = HashMap::new();
+    let mut ft_buf = split_bytes(buf, ft_len)?;
+    let mut fields = vec![];
 
     while ft_buf.has_remaining() {
-        let field_name = decode_short_string(&mut ft_buf);
-        let field_value = decode_value(&mut ft_buf);
+        let field_name = decode_short_string(&mut ft_buf)?;
+        let field_value = decode_value(&mut ft_buf, depth)?;
 
-        table.insert(field_name, field_value);
+        fields.push((field_name, field_value));
     }
 
-    Some(table)
+    Ok(Some(fields.into_iter().collect()))
 }
 
 fn encode_method_frame(
@@ -402,293 +499,834 @@ fn encode_method_frame(
     channel: Channel,
     cm: ClassMethod,
     args: &MethodFrameArgs,
-) {
-    buf.put_u8(1u8);
+) -> Result<()> {
+    buf.put_u8(FRAME_METHOD);
     buf.put_u16(channel);
 
     let mut fr = BytesMut::with_capacity(4096);
     fr.put_u32(cm);
 
-    match args {
-        MethodFrameArgs::ConnectionStart(args) => encode_connection_start(&mut fr, args),
-        MethodFrameArgs::ConnectionStartOk(args) => encode_connection_start_ok(&mut fr, args),
-        MethodFrameArgs::ConnectionTune(args) => encode_connection_tune(&mut fr, args),
-        MethodFrameArgs::ConnectionTuneOk(args) => encode_connection_tune_ok(&mut fr, args),
-        MethodFrameArgs::ConnectionOpen(args) => encode_connection_open(&mut fr, args),
-        MethodFrameArgs::ConnectionOpenOk => encode_connection_open_ok(&mut fr),
-        MethodFrameArgs::ConnectionClose(args) => encode_connection_close(&mut fr, args),
-        MethodFrameArgs::ConnectionCloseOk => (),
-        MethodFrameArgs::ChannelOpen => encode_channel_open(&mut fr),
-        MethodFrameArgs::ChannelOpenOk => encode_channel_open_ok(&mut fr),
-        MethodFrameArgs::ChannelClose(args) => encode_channel_close(&mut fr, args),
-        MethodFrameArgs::ChannelCloseOk => (),
-        MethodFrameArgs::ExchangeDeclare(args) => encode_exchange_declare(&mut fr, args),
-        MethodFrameArgs::ExchangeDeclareOk => (),
-        MethodFrameArgs::ExchangeBind(args) => encode_exchange_bind(&mut fr, args),
-        MethodFrameArgs::ExchangeBindOk => (),
-        MethodFrameArgs::QueueDeclare(args) => encode_queue_declare(&mut fr, args),
-        MethodFrameArgs::QueueDeclareOk(args) => encode_queue_declare_ok(&mut fr, args),
-        MethodFrameArgs::QueueBind(args) => encode_queue_bind(&mut fr, args),
-        MethodFrameArgs::QueueBindOk => (),
-        MethodFrameArgs::BasicPublish(args) => encode_basic_publish(&mut fr, args),
-        MethodFrameArgs::BasicConsume(args) => encode_basic_consume(&mut fr, args),
-        MethodFrameArgs::BasicConsumeOk(args) => encode_basic_consume_ok(&mut fr, args),
-        MethodFrameArgs::BasicDeliver(args) => encode_basic_deliver(&mut fr, args)
-    }
+    encode_method_frame_args(&mut fr, args)?;
 
     buf.put_u32(fr.len() as u32);
     buf.put(fr);
-    buf.put_u8(0xCE);
-}
+    buf.put_u8(FRAME_END);
 
-fn encode_connection_start(mut buf: &mut BytesMut, args: &ConnectionStartArgs) {
-    buf.put_u8(args.version_major);
-    buf.put_u8(args.version_minor);
-    encode_field_table(&mut buf, args.properties.as_ref());
-    encode_long_string(&mut buf, &args.mechanisms);
-    encode_long_string(&mut buf, &args.locales);
+    Ok(())
 }
 
-fn encode_connection_start_ok(mut buf: &mut BytesMut, args: &ConnectionStartOkArgs) {
-    encode_field_table(&mut buf, args.properties.as_ref());
-    encode_short_string(&mut buf, &args.mechanism);
-    encode_long_string(&mut buf, &args.response);
-    encode_short_string(&mut buf, &args.locale);
-}
+fn encode_content_header_frame(buf: &mut BytesMut, hf: &ContentHeaderFrame) -> Result<()> {
+    buf.put_u8(FRAME_HEADER);
+    buf.put_u16(hf.channel);
 
-fn encode_connection_tune(buf: &mut BytesMut, args: &ConnectionTuneArgs) {
-    buf.put_u16(args.channel_max);
-    buf.put_u32(args.frame_max);
-    buf.put_u16(args.heartbeat);
-}
+    let mut fr_buf = BytesMut::with_capacity(4096);
+    fr_buf.put_u16(hf.class_id);
+    fr_buf.put_u16(hf.weight);
+    fr_buf.put_u64(hf.body_size);
+    fr_buf.put_u16(hf.properties.flags().bits());
 
-fn encode_connection_tune_ok(buf: &mut BytesMut, args: &ConnectionTuneOkArgs) {
-    buf.put_u16(args.channel_max);
-    buf.put_u32(args.frame_max);
-    buf.put_u16(args.heartbeat);
-}
+    encode_basic_properties(&mut fr_buf, &hf.properties)?;
 
-fn encode_connection_open(buf: &mut BytesMut, args: &ConnectionOpenArgs) {
-    encode_short_string(buf, &args.virtual_host);
-    encode_short_string(buf, "");
-    let mut flags = 0x00;
+    buf.put_u32(fr_buf.len() as u32);
+    buf.put(fr_buf);
+    buf.put_u8(FRAME_END);
 
-    if args.insist {
-        flags |= 0x01;
+    Ok(())
+}
+
+fn encode_basic_properties(buf: &mut BytesMut, props: &BasicProperties) -> Result<()> {
+    if let Some(ref s) = props.content_type {
+        encode_short_string(buf, s)?;
+    }
+    if let Some(ref s) = props.content_encoding {
+        encode_short_string(buf, s)?;
+    }
+    if let Some(ref headers) = props.headers {
+        encode_field_table2(buf, headers)?;
+    }
+    if let Some(v) = props.delivery_mode {
+        buf.put_u8(v);
+    }
+    if let Some(v) = props.priority {
+        buf.put_u8(v);
+    }
+    if let Some(ref s) = props.correlation_id {
+        encode_short_string(buf, s)?;
+    }
+    if let Some(ref s) = props.reply_to {
+        encode_short_string(buf, s)?;
+    }
+    if let Some(ref s) = props.expiration {
+        encode_short_string(buf, s)?;
+    }
+    if let Some(ref s) = props.message_id {
+        encode_short_string(buf, s)?;
+    }
+    if let Some(v) = props.timestamp {
+        buf.put_u64(v);
+    }
+    if let Some(ref s) = props.message_type {
+        encode_short_string(buf, s)?;
+    }
+    if let Some(ref s) = props.user_id {
+        encode_short_string(buf, s)?;
+    }
+    if let Some(ref s) = props.app_id {
+        encode_short_string(buf, s)?;
     }
 
-    buf.put_u8(flags);
+    Ok(())
 }
 
-fn encode_connection_open_ok(buf: &mut BytesMut) {
-    // encode empty short string
-    buf.put_u8(0);
-}
+fn encode_content_body_frame(buf: &mut BytesMut, bf: &ContentBodyFrame) -> Result<()> {
+    buf.put_u8(FRAME_BODY);
+    buf.put_u16(bf.channel);
 
-fn encode_connection_close(mut buf: &mut BytesMut, args: &ConnectionCloseArgs) {
-    buf.put_u16(args.code);
-    encode_short_string(&mut buf, &args.text);
-    buf.put_u16(args.class_id);
-    buf.put_u16(args.method_id);
-}
+    buf.put_u32(long_size(bf.body.len(), "Content body")?);
+    buf.put(bf.body.as_ref());
+    buf.put_u8(FRAME_END);
 
-fn encode_channel_open(buf: &mut BytesMut) {
-    // encode empty short string
-    buf.put_u8(0);
+    Ok(())
 }
 
-fn encode_channel_open_ok(buf: &mut BytesMut) {
-    // encode empty long string
+fn encode_heartbeat_frame(buf: &mut BytesMut, channel: Channel) {
+    buf.put_u8(FRAME_HEARTBEAT);
+    buf.put_u16(channel);
     buf.put_u32(0);
+    buf.put_u8(FRAME_END);
 }
 
-fn encode_channel_close(mut buf: &mut BytesMut, args: &ChannelCloseArgs) {
-    buf.put_u16(args.code);
-    encode_short_string(&mut buf, &args.text);
-    buf.put_u16(args.class_id);
-    buf.put_u16(args.method_id);
-}
+fn encode_short_string(buf: &mut BytesMut, s: &str) -> Result<()> {
+    if s.len() > 255 {
+        return frame_error!(
+            SYNTAX_ERROR,
+            format!("Short string is longer than 255 bytes: {}...", truncate_str(s, 32))
+        );
+    }
 
-fn encode_exchange_declare(mut buf: &mut BytesMut, args: &ExchangeDeclareArgs) {
-    buf.put_u16(0);
-    encode_short_string(&mut buf, &args.exchange_name);
-    encode_short_string(&mut buf, &args.exchange_type);
-    buf.put_u8(args.flags.bits());
-    encode_empty_field_table(&mut buf);
-}
+    buf.put_u8(s.len() as u8);
+    buf.put(s.as_bytes());
 
-fn encode_exchange_bind(mut buf: &mut BytesMut, args: &ExchangeBindArgs) {
-    buf.put_u16(0);
-    encode_short_string(&mut buf, &args.destination);
-    encode_short_string(&mut buf, &args.source);
-    encode_short_string(&mut buf, &args.routing_key);
-    buf.put_u8(if args.no_wait { 1 } else { 0 });
-    encode_empty_field_table(&mut buf);
+    Ok(())
 }
 
-fn encode_queue_declare(mut buf: &mut BytesMut, args: &QueueDeclareArgs) {
-    buf.put_u16(0);
-    encode_short_string(&mut buf, &args.name);
-    buf.put_u8(args.flags.bits());
-    encode_empty_field_table(&mut buf);
-}
+fn encode_long_string(buf: &mut BytesMut, s: &[u8]) -> Result<()> {
+    buf.put_u32(long_size(s.len(), "Long string")?);
+    buf.put(s);
 
-fn encode_queue_declare_ok(mut buf: &mut BytesMut, args: &QueueDeclareOkArgs) {
-    encode_short_string(&mut buf, &args.name);
-    buf.put_u32(args.message_count);
-    buf.put_u32(args.consumer_count);
+    Ok(())
 }
 
-fn encode_queue_bind(mut buf: &mut BytesMut, args: &QueueBindArgs) {
-    buf.put_u16(0);
-    encode_short_string(&mut buf, &args.queue_name);
-    encode_short_string(&mut buf, &args.exchange_name);
-    encode_short_string(&mut buf, &args.routing_key);
-    buf.put_u8(if args.no_wait { 1 } else { 0 });
-    encode_empty_field_table(&mut buf);
+/// Size prefix of long strings, byte arrays, field arrays and field tables.
+fn long_size(len: usize, what: &str) -> Result<u32> {
+    match u32::try_from(len) {
+        Ok(size) => Ok(size),
+        Err(_) => frame_error!(SYNTAX_ERROR, format!("{} is too large: {} bytes", what, len)),
+    }
 }
 
-fn encode_basic_consume(mut buf: &mut BytesMut, args: &BasicConsumeArgs) {
-    buf.put_u16(0);
-    encode_short_string(&mut buf, &args.queue);
-    encode_short_string(&mut buf, &args.consumer_tag);
-    buf.put_u8(args.flags.bits());
-    encode_empty_field_table(&mut buf);
-}
+/// The beginning of the string for error messages, cut at a character boundary.
+fn truncate_str(s: &str, max: usize) -> &str {
+    let end = (0..=max.min(s.len())).rev().find(|i| s.is_char_boundary(*i)).unwrap_or(0);
 
-fn encode_basic_consume_ok(mut buf: &mut BytesMut, args: &BasicConsumeOkArgs) {
-    encode_short_string(&mut buf, &args.consumer_tag);
+    &s[..end]
 }
 
-fn encode_basic_deliver(mut buf: &mut BytesMut, args: &BasicDeliverArgs) {
-    encode_short_string(&mut buf, &args.consumer_tag);
-    buf.put_u64(args.delivery_tag);
-    buf.put_u8(if args.redelivered { 1 } else { 0 });
-    encode_short_string(&mut buf, &args.exchange_name);
-    encode_short_string(&mut buf, &args.routing_key);
+fn encode_empty_field_table(buf: &mut BytesMut) {
+    buf.put_u32(0);
 }
 
-        //BASIC_DELIVER => vec![t_ss!(), t_u64!(), t_u8!(), t_ss!(), t_ss!()],
-fn encode_basic_publish(mut buf: &mut BytesMut, args: &BasicPublishArgs) {
-    buf.put_u16(0);
-    encode_short_string(&mut buf, &args.exchange_name);
-    encode_short_string(&mut buf, &args.routing_key);
-    buf.put_u8(args.flags.bits());
+fn encode_field_table(buf: &mut BytesMut, ft: Option<&FieldTable>) -> Result<()> {
+    match ft {
+        None => {
+            buf.put_u32(0);
+            Ok(())
+        }
+        Some(t) => encode_field_table2(buf, t),
+    }
 }
 
-fn encode_content_header_frame(buf: &mut BytesMut, hf: &ContentHeaderFrame) {
-    buf.put_u8(2u8);
-    buf.put_u16(hf.channel);
+fn encode_field_table2(buf: &mut BytesMut, ft: &FieldTable) -> Result<()> {
+    let mut ft_buf = BytesMut::with_capacity(4096);
 
-    let mut fr_buf = BytesMut::with_capacity(4096);
-    fr_buf.put_u16(hf.class_id);
-    fr_buf.put_u16(hf.weight);
-    fr_buf.put_u64(hf.body_size);
-    fr_buf.put_u16(hf.prop_flags);
+    for (name, value) in ft {
+        encode_short_string(&mut ft_buf, name)?;
+        encode_value(&mut ft_buf, value)?;
+    }
 
-    // TODO encode property list here
+    buf.put_u32(long_size(ft_buf.len(), "Field table")?);
+    buf.put(ft_buf);
 
-    buf.put_u32(fr_buf.len() as u32);
-    buf.put(fr_buf);
-    buf.put_u8(0xCE);
+    Ok(())
 }
 
-fn encode_content_body_frame(buf: &mut BytesMut, bf: &ContentBodyFrame) {
-    buf.put_u8(3u8);
-    buf.put_u16(bf.channel);
+fn encode_value(buf: &mut BytesMut, value: &AMQPFieldValue) -> Result<()> {
+    match value {
+        AMQPFieldValue::Bool(v) => {
+            buf.put_u8(b't');
+            buf.put_u8(if *v { 1 } else { 0 });
+        }
+        AMQPFieldValue::I8(v) => {
+            buf.put_u8(b'b');
+            buf.put_i8(*v);
+        }
+        AMQPFieldValue::U8(v) => {
+            buf.put_u8(b'B');
+            buf.put_u8(*v);
+        }
+        AMQPFieldValue::I16(v) => {
+            buf.put_u8(b's');
+            buf.put_i16(*v);
+        }
+        AMQPFieldValue::U16(v) => {
+            buf.put_u8(b'u');
+            buf.put_u16(*v);
+        }
+        AMQPFieldValue::I32(v) => {
+            buf.put_u8(b'I');
+            buf.put_i32(*v);
+        }
+        AMQPFieldValue::U32(v) => {
+            buf.put_u8(b'i');
+            buf.put_u32(*v);
+        }
+        AMQPFieldValue::I64(v) => {
+            buf.put_u8(b'l');
+            buf.put_i64(*v);
+        }
+        AMQPFieldValue::F32(v) => {
+            buf.put_u8(b'f');
+            buf.put_f32(*v);
+        }
+        AMQPFieldValue::F64(v) => {
+            buf.put_u8(b'd');
+            buf.put_f64(*v);
+        }
+        AMQPFieldValue::Decimal(scale, v) => {
+            buf.put_u8(b'D');
+            buf.put_u8(*scale);
+            buf.put_u32(*v);
+        }
+        AMQPFieldValue::LongString(v) => {
+            buf.put_u8(b'S');
+            encode_long_string(buf, v.as_bytes())?;
+        }
+        AMQPFieldValue::ByteArray(v) => {
+            buf.put_u8(b'x');
+            buf.put_u32(long_size(v.len(), "Byte array")?);
+            buf.put(v.as_slice());
+        }
+        AMQPFieldValue::Array(values) => {
+            let mut array_buf = BytesMut::with_capacity(256);
 
-    let mut fr_buf = BytesMut::with_capacity(bf.body.len());
-    fr_buf.put(bf.body.as_slice());
+            for v in values {
+                encode_value(&mut array_buf, v)?;
+            }
 
-    buf.put_u32(fr_buf.len() as u32);
-    buf.put(fr_buf);
-    buf.put_u8(0xCE);
-}
+            buf.put_u8(b'A');
+            buf.put_u32(long_size(array_buf.len(), "Field array")?);
+            buf.put(array_buf);
+        }
+        AMQPFieldValue::Timestamp(v) => {
+            buf.put_u8(b'T');
+            buf.put_u64(*v);
+        }
+        AMQPFieldValue::EmptyFieldTable => {
+            buf.put_u8(b'F');
+            encode_empty_field_table(buf);
+        }
+        AMQPFieldValue::FieldTable(v) => {
+            buf.put_u8(b'F');
 
-fn encode_heartbeat_frame(buf: &mut BytesMut, channel: Channel) {
-    buf.put_u16(channel);
-    buf.put_u32(0);
-    buf.put_u8(0xCE);
-}
+            // TODO we are copying here
+            encode_field_table2(buf, v)?;
+        }
+        AMQPFieldValue::Void => buf.put_u8(b'V'),
+    }
 
-fn encode_short_string(buf: &mut BytesMut, s: &str) {
-    // TODO assert! that size is below 256
-    buf.put_u8(s.len() as u8);
-    buf.put(s.as_bytes());
+    Ok(())
 }
 
-fn encode_long_string(buf: &mut BytesMut, s: &str) {
-    buf.put_u32(s.len() as u32);
-    buf.put(s.as_bytes());
-}
+// Encoder and decoder of the method frame arguments generated from the AMQP 0-9-1 spec.
+include!(concat!(env!("OUT_DIR"), "/codec_methods.rs"));
 
-fn encode_empty_field_table(buf: &mut BytesMut) {
-    buf.put_u32(0);
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn encode_field_table(mut buf: &mut BytesMut, ft: Option<&HashMap<String, AMQPFieldValue>>) {
-    match ft {
-        None => buf.put_u32(0),
-        Some(t) => encode_field_table2(&mut buf, t),
+    /// Encode and decode the frames and check that they didn't change. Argument structs don't
+    /// implement `PartialEq`, so the debug format is compared.
+    fn assert_round_trip(frames: Vec<AMQPFrame>) {
+        let mut codec = AMQPCodec::default();
+
+        for frame in frames {
+            let mut buf = BytesMut::new();
+            codec.encode(frame.clone(), &mut buf).unwrap();
+
+            let decoded = codec.decode(&mut buf).unwrap().unwrap();
+
+            assert!(buf.is_empty());
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", frame));
+        }
     }
-}
 
-fn encode_field_table2(buf: &mut BytesMut, ft: &HashMap<String, AMQPFieldValue>) {
-    let mut ft_buf = BytesMut::with_capacity(4096);
+    #[test]
+    fn table_fields_without_length_are_decoded() {
+        let mut table = FieldTable::new();
+        table.insert("LOGIN".into(), AMQPFieldValue::LongString("guest".into()));
+        table.insert("PASSWORD".into(), AMQPFieldValue::LongString("secret".into()));
 
-    for (name, value) in ft {
-        encode_short_string(&mut ft_buf, &name);
+        let mut buf = BytesMut::new();
+        encode_field_table(&mut buf, Some(&table)).unwrap();
 
-        match value {
-            AMQPFieldValue::Bool(v) => {
-                ft_buf.put_u8(b't');
-                ft_buf.put_u8(if *v { 1 } else { 0 });
-            }
-            AMQPFieldValue::LongString(v) => {
-                ft_buf.put_u8(b'S');
-                ft_buf.put_u32(v.len() as u32);
-                ft_buf.put(v.as_bytes());
-            }
-            AMQPFieldValue::EmptyFieldTable => encode_empty_field_table(&mut ft_buf),
-            AMQPFieldValue::FieldTable(v) => {
-                ft_buf.put_u8(b'F');
+        let decoded = decode_table_fields(&buf[4..]).unwrap();
+
+        assert_eq!(decoded, table);
+        assert!(decode_table_fields(&buf[4..10]).is_err());
+    }
+
+    #[test]
+    fn heartbeat_frame_round_trip() {
+        let mut codec = AMQPCodec::default();
+        let mut buf = BytesMut::new();
+
+        codec.encode(AMQPFrame::Heartbeat(0), &mut buf).unwrap();
+
+        assert_eq!(&buf[..], &[8, 0, 0, 0, 0, 0, 0, 0xCE]);
+
+        assert_round_trip(vec![AMQPFrame::Heartbeat(0)]);
+    }
+
+    #[test]
+    fn field_table_values_round_trip() {
+        let mut nested = FieldTable::new();
+        nested.insert("level".into(), AMQPFieldValue::U8(2));
+
+        let mut args = FieldTable::new();
+        args.insert("bool".into(), AMQPFieldValue::Bool(true));
+        args.insert("i8".into(), AMQPFieldValue::I8(-8));
+        args.insert("u8".into(), AMQPFieldValue::U8(8));
+        args.insert("i16".into(), AMQPFieldValue::I16(-16));
+        args.insert("u16".into(), AMQPFieldValue::U16(16));
+        args.insert("i32".into(), AMQPFieldValue::I32(-32));
+        args.insert("u32".into(), AMQPFieldValue::U32(32));
+        args.insert("x-message-ttl".into(), AMQPFieldValue::I64(60_000));
+        args.insert("f32".into(), AMQPFieldValue::F32(1.5));
+        args.insert("f64".into(), AMQPFieldValue::F64(-2.25));
+        args.insert("decimal".into(), AMQPFieldValue::Decimal(2, 12345));
+        args.insert("string".into(), AMQPFieldValue::LongString("value".into()));
+        args.insert("bytes".into(), AMQPFieldValue::ByteArray(vec![0, 1, 2, 255]));
+        args.insert(
+            "array".into(),
+            AMQPFieldValue::Array(vec![AMQPFieldValue::I32(1), AMQPFieldValue::LongString("two".into())]),
+        );
+        args.insert("timestamp".into(), AMQPFieldValue::Timestamp(1_600_000_000));
+        args.insert("empty".into(), AMQPFieldValue::EmptyFieldTable);
+        args.insert("nested".into(), AMQPFieldValue::FieldTable(Box::new(nested)));
+        args.insert("void".into(), AMQPFieldValue::Void);
+
+        let mut buf = BytesMut::new();
+        encode_field_table(&mut buf, Some(&args)).unwrap();
+
+        let decoded = decode_field_table(&mut buf).unwrap().unwrap();
+
+        assert!(buf.is_empty());
+        assert_eq!(decoded, args);
+    }
 
-                // TODO we are copying here
-                encode_field_table2(&mut ft_buf, v);
+    #[test]
+    fn content_header_properties_round_trip() {
+        let mut headers = FieldTable::new();
+        headers.insert("retries".into(), AMQPFieldValue::I32(3));
+
+        let properties = BasicProperties {
+            content_type: Some("application/json".into()),
+            headers: Some(headers),
+            delivery_mode: Some(2),
+            correlation_id: Some("corr-1".into()),
+            reply_to: Some("replies".into()),
+            timestamp: Some(1_600_000_000),
+            app_id: Some("test".into()),
+            ..Default::default()
+        };
+
+        let mut codec = AMQPCodec::default();
+        let mut buf = BytesMut::new();
+        let header = content_header_with_properties(3, 128, properties.clone());
+        codec.encode(AMQPFrame::ContentHeader(header), &mut buf).unwrap();
+
+        match codec.decode(&mut buf).unwrap() {
+            Some(AMQPFrame::ContentHeader(header)) => {
+                assert_eq!(header.channel, 3);
+                assert_eq!(header.body_size, 128);
+                assert_eq!(header.properties, properties);
             }
+            f => panic!("Unexpected frame {:?}", f),
         }
     }
 
-    buf.put_u32(ft_buf.len() as u32);
-    buf.put(ft_buf);
-}
+    #[test]
+    fn content_header_property_flags_are_calculated() {
+        let properties = BasicProperties {
+            content_type: Some("text/plain".into()),
+            priority: Some(5),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            properties.flags(),
+            HeaderPropertyFlags::CONTENT_TYPE | HeaderPropertyFlags::PRIORITY
+        );
+        assert_eq!(properties.flags().bits(), 0x8800);
+    }
 
-#[allow(dead_code)]
-fn dump(buf: &BytesMut) {
-    let mut cloned = buf.clone();
-    let mut i: usize = 0;
-    let mut text: Vec<u8> = Vec::new();
+    #[test]
+    fn decode_original_spec_type_tags() {
+        let mut buf = BytesMut::new();
+        buf.put_u8(b'U');
+        buf.put_i16(-2);
+        buf.put_u8(b'L');
+        buf.put_i64(-3);
+
+        assert_eq!(decode_value(&mut buf, 0).unwrap(), AMQPFieldValue::I16(-2));
+        assert_eq!(decode_value(&mut buf, 0).unwrap(), AMQPFieldValue::I64(-3));
+    }
+
+    fn encoded_frame(frame: AMQPFrame) -> BytesMut {
+        let mut buf = BytesMut::new();
+        AMQPCodec::default().encode(frame, &mut buf).unwrap();
+
+        buf
+    }
 
-    println!("---");
+    fn frame_error_code(result: Result<Option<AMQPFrame>>) -> u16 {
+        result.unwrap_err().downcast::<crate::FrameError>().unwrap().code
+    }
 
-    while cloned.has_remaining() {
-        let b = cloned.get_u8();
+    #[test]
+    fn decode_waits_for_complete_frame() {
+        let encoded = encoded_frame(queue_declare(1, "queue"));
+        let mut codec = AMQPCodec::default();
+        let mut buf = BytesMut::new();
 
-        print!("{:02X} ", b);
+        for b in &encoded[..encoded.len() - 1] {
+            buf.put_u8(*b);
 
-        if (b as char).is_alphanumeric() {
-            text.push(b);
-        } else {
-            text.push(b'.');
+            assert!(codec.decode(&mut buf).unwrap().is_none());
         }
 
-        i += 1;
+        buf.put_u8(encoded[encoded.len() - 1]);
+
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_waits_for_complete_protocol_header() {
+        let mut codec = AMQPCodec::default();
+        let mut buf = BytesMut::from(&b"AMQP"[..]);
+
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+
+        buf.put(&b"\x00\x00\x09\x01"[..]);
+
+        assert!(matches!(codec.decode(&mut buf).unwrap(), Some(AMQPFrame::Header)));
+    }
+
+    #[test]
+    fn decode_unsupported_protocol_version_is_error() {
+        let mut codec = AMQPCodec::default();
+        let mut buf = BytesMut::from(&b"AMQP\x01\x01\x00\x0A"[..]);
+
+        let err = codec.decode(&mut buf).unwrap_err();
+        let err = err.downcast_ref::<ProtocolHeaderError>().unwrap();
+
+        assert_eq!(err.version(), Some("1-0-10".into()));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_non_amqp_protocol_header_is_error() {
+        let mut codec = AMQPCodec::default();
+        let mut buf = BytesMut::from(&b"ABCDEFGH"[..]);
+
+        let err = codec.decode(&mut buf).unwrap_err();
+
+        assert_eq!(err.downcast_ref::<ProtocolHeaderError>().unwrap().version(), None);
+    }
+
+    #[test]
+    fn decode_frame_above_frame_max_is_frame_error() {
+        let mut codec = AMQPCodec::new(4096);
+        // only the frame header arrived, but it says that the frame is 1 MB
+        let mut buf = BytesMut::from(&b"\x03\x00\x01\x00\x10\x00\x00"[..]);
+
+        assert_eq!(frame_error_code(codec.decode(&mut buf)), FRAME_ERROR);
+    }
+
+    #[test]
+    fn encode_frame_above_frame_max_is_frame_error() {
+        let mut codec = AMQPCodec::new(4096);
+        let mut buf = BytesMut::new();
+
+        codec.encode(AMQPFrame::ContentHeader(content_header(1, 4088 + 4089)), &mut buf).unwrap();
+        codec.encode(AMQPFrame::ContentBody(content_body(1, &[0u8; 4088])), &mut buf).unwrap();
+        let len = buf.len();
+
+        let result = codec.encode(AMQPFrame::ContentBody(content_body(1, &[0u8; 4089])), &mut buf);
+
+        assert_eq!(result.unwrap_err().downcast::<crate::FrameError>().unwrap().code, FRAME_ERROR);
+        assert_eq!(buf.len(), len);
+    }
+
+    #[test]
+    fn short_string_longer_than_255_bytes_is_syntax_error() {
+        let mut codec = AMQPCodec::default();
+        let mut buf = BytesMut::new();
+
+        let result = codec.encode(basic_publish(1, "exchange", &"k".repeat(300)), &mut buf);
+
+        assert_eq!(result.unwrap_err().downcast::<crate::FrameError>().unwrap().code, SYNTAX_ERROR);
+        assert!(buf.is_empty());
+
+        let mut headers = FieldTable::new();
+        headers.insert("é".repeat(128), AMQPFieldValue::Bool(true));
+
+        let result = encode_field_table(&mut buf, Some(&headers));
+
+        assert_eq!(result.unwrap_err().downcast::<crate::FrameError>().unwrap().code, SYNTAX_ERROR);
+    }
+
+    #[test]
+    fn content_body_not_matching_body_size_is_error() {
+        let mut codec = AMQPCodec::default();
+        let mut buf = BytesMut::new();
 
-        if i % 16 == 0 {
-            println!("{}", std::str::from_utf8(&text).unwrap_or_default());
-            text.clear();
+        let short = vec![
+            basic_publish(1, "exchange", "key"),
+            AMQPFrame::ContentHeader(content_header(1, 10)),
+            AMQPFrame::ContentBody(content_body(1, b"hello")),
+        ];
+
+        let result = codec.encode(short, &mut buf);
+
+        assert_eq!(result.unwrap_err().downcast::<crate::FrameError>().unwrap().code, FRAME_ERROR);
+        assert!(buf.is_empty());
+
+        let long = vec![
+            AMQPFrame::ContentHeader(content_header(1, 2)),
+            AMQPFrame::ContentBody(content_body(1, b"hello")),
+        ];
+
+        assert!(codec.encode(long, &mut buf).is_err());
+        assert!(buf.is_empty());
+
+        let body_only = AMQPFrame::ContentBody(content_body(1, b"hello"));
+        let result = codec.encode(body_only, &mut buf);
+
+        assert_eq!(result.unwrap_err().downcast::<crate::FrameError>().unwrap().code, UNEXPECTED_FRAME);
+
+        // the failed batches didn't leave a half content behind
+        let ok = vec![
+            basic_publish(1, "exchange", "key"),
+            AMQPFrame::ContentHeader(content_header(1, 5)),
+            AMQPFrame::ContentBody(content_body(1, b"hel")),
+            AMQPFrame::ContentBody(content_body(1, b"lo")),
+        ];
+
+        assert!(codec.encode(ok, &mut buf).is_ok());
+    }
+
+    #[test]
+    fn frame_max_can_be_changed_after_tuning() {
+        let mut codec = AMQPCodec::new(DEFAULT_FRAME_MAX);
+        let mut buf = BytesMut::new();
+
+        codec.encode(AMQPFrame::ContentHeader(content_header(1, 2 * 8192)), &mut buf).unwrap();
+        codec.encode(AMQPFrame::ContentBody(content_body(1, &[0u8; 8192])), &mut buf).unwrap();
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+
+        codec.set_frame_max(4096);
+
+        assert!(codec.encode(AMQPFrame::ContentBody(content_body(1, &[0u8; 8192])), &mut buf).is_err());
+    }
+
+    #[test]
+    fn decode_invalid_frame_end_is_frame_error() {
+        let mut buf = encoded_frame(channel_open(1));
+        let last = buf.len() - 1;
+        buf[last] = 0x00;
+
+        assert_eq!(frame_error_code(AMQPCodec::default().decode(&mut buf)), FRAME_ERROR);
+    }
+
+    #[test]
+    fn decode_unknown_frame_type_is_frame_error() {
+        let mut buf = BytesMut::from(&b"\x05\x00\x01\x00\x00\x00\x00\xCE"[..]);
+
+        assert_eq!(frame_error_code(AMQPCodec::default().decode(&mut buf)), FRAME_ERROR);
+    }
+
+    #[test]
+    fn decode_unknown_method_is_not_implemented() {
+        let mut buf = BytesMut::from(&b"\x01\x00\x01\x00\x00\x00\x04\x00\xFF\x00\x01\xCE"[..]);
+
+        assert_eq!(frame_error_code(AMQPCodec::default().decode(&mut buf)), NOT_IMPLEMENTED);
+    }
+
+    #[test]
+    fn declared_frame_size_is_not_allocated_upfront() {
+        let mut codec = AMQPCodec::new(0);
+        let mut buf = BytesMut::from(&b"\x01\x00\x01\xFF\xFF\xFF\xF0"[..]);
+
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert!(buf.capacity() < 1024);
+    }
+
+    #[test]
+    fn decode_truncated_arguments_is_syntax_error() {
+        // queue.declare with a queue name longer than the frame
+        let mut buf = BytesMut::from(&b"\x01\x00\x01\x00\x00\x00\x08\x00\x32\x00\x0A\x00\x00\x40q\xCE"[..]);
+
+        assert_eq!(frame_error_code(AMQPCodec::default().decode(&mut buf)), SYNTAX_ERROR);
+    }
+
+    #[test]
+    fn decode_invalid_utf8_is_syntax_error() {
+        let mut buf = BytesMut::from(&b"\x01\x00\x01\x00\x00\x00\x0A\x00\x32\x00\x0A\x00\x00\x02\xC3\x28\x00\xCE"[..]);
+
+        assert_eq!(frame_error_code(AMQPCodec::default().decode(&mut buf)), SYNTAX_ERROR);
+    }
+
+    #[test]
+    fn decode_unknown_field_value_type_is_syntax_error() {
+        let mut buf = BytesMut::from(&b"?"[..]);
+
+        assert!(decode_value(&mut buf, 0).is_err());
+    }
+
+    fn nested_arrays(depth: usize) -> BytesMut {
+        let mut buf = BytesMut::new();
+
+        for level in 0..depth {
+            buf.put_u8(b'A');
+            buf.put_u32(5 * (depth - level - 1) as u32);
         }
+
+        buf
     }
 
-    println!("---");
+    #[test]
+    fn deeply_nested_field_value_is_syntax_error() {
+        assert!(decode_value(&mut nested_arrays(MAX_NESTING_DEPTH), 0).is_ok());
+
+        let result = decode_value(&mut nested_arrays(26_000), 0);
+
+        assert_eq!(result.unwrap_err().downcast::<crate::FrameError>().unwrap().code, SYNTAX_ERROR);
+
+        let mut fields = BytesMut::from(&b"\x01a"[..]);
+        fields.extend_from_slice(&nested_arrays(26_000));
+
+        assert!(decode_table_fields(&fields).is_err());
+    }
+
+    #[test]
+    fn field_table_is_encoded_in_insertion_order() {
+        let table = FieldTable::builder().bool("b", true).i32("a", 1).build();
+        let mut buf = BytesMut::new();
+
+        encode_field_table(&mut buf, Some(&table)).unwrap();
+
+        assert_eq!(&buf[..], &b"\x00\x00\x00\x0B\x01bt\x01\x01aI\x00\x00\x00\x01"[..]);
+
+        let decoded = decode_field_table(&mut buf).unwrap().unwrap();
+        let names = decoded.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["b", "a"]);
+    }
+
+    #[test]
+    fn duplicated_field_keeps_position_and_gets_last_value() {
+        let mut fields = BytesMut::new();
+
+        for i in 0..22_000 {
+            let name = format!("k{}", i);
+
+            fields.put_u8(name.len() as u8);
+            fields.put(name.as_bytes());
+            fields.put_u8(b'V');
+        }
+
+        fields.put(&b"\x02k1I\x00\x00\x00\x07"[..]);
+
+        let mut buf = BytesMut::new();
+        buf.put_u32(fields.len() as u32);
+        buf.put(fields);
+
+        let decoded = decode_field_table(&mut buf).unwrap().unwrap();
+
+        assert_eq!(decoded.len(), 22_000);
+        assert_eq!(decoded.iter().nth(1), Some((&"k1".to_string(), &AMQPFieldValue::I32(7))));
+    }
+
+    #[test]
+    fn field_table_typed_getters() {
+        let table = FieldTable::builder()
+            .value("x-max-length", AMQPFieldValue::U16(100))
+            .i64("x-message-ttl", 60_000)
+            .str("x-queue-mode", "lazy")
+            .value("nested", AMQPFieldValue::EmptyFieldTable)
+            .build();
+
+        assert_eq!(table.get_i64("x-max-length"), Some(100));
+        assert_eq!(table.get_i64("x-message-ttl"), Some(60_000));
+        assert_eq!(table.get_str("x-queue-mode"), Some("lazy"));
+        assert_eq!(table.get_i64("x-queue-mode"), None);
+        assert_eq!(table.get_table("nested").map(FieldTable::len), Some(0));
+        assert_eq!(table.get_bool("missing"), None);
+    }
+
+    #[test]
+    fn queue_declare_arguments_are_encoded() {
+        let mut queue_args = FieldTable::new();
+        queue_args.insert("x-message-ttl".into(), AMQPFieldValue::I32(1000));
+
+        let frame = AMQPFrame::Method(
+            1,
+            QUEUE_DECLARE,
+            MethodFrameArgs::QueueDeclare(QueueDeclareArgs {
+                queue: "queue".into(),
+                flags: QueueDeclareFlags::DURABLE,
+                arguments: Some(queue_args.clone()),
+            }),
+        );
+
+        let mut codec = AMQPCodec::default();
+        let mut buf = BytesMut::new();
+        codec.encode(frame, &mut buf).unwrap();
+
+        match codec.decode(&mut buf).unwrap() {
+            Some(AMQPFrame::Method(1, QUEUE_DECLARE, MethodFrameArgs::QueueDeclare(args))) => {
+                assert_eq!(args.queue, "queue");
+                assert_eq!(args.arguments, Some(queue_args));
+            }
+            f => panic!("Unexpected frame {:?}", f),
+        }
+    }
+
+    #[test]
+    fn basic_methods_round_trip() {
+        let frames = vec![
+            basic_qos(1, 0, 10, true),
+            basic_qos_ok(1),
+            basic_cancel(1, "ctag", false),
+            basic_cancel_ok(1, "ctag"),
+            basic_return(1, 312, "NO_ROUTE", "exchange", "rkey"),
+            basic_get(1, "queue", true),
+            basic_get_ok(1, 42, true, "exchange", "rkey", 7),
+            basic_get_empty(1),
+            basic_ack(1, 42, true),
+            basic_reject(1, 42, false),
+            basic_nack(1, 42, Some(BasicNackFlags::MULTIPLE | BasicNackFlags::REQUEUE)),
+            basic_recover_async(1, true),
+            basic_recover(1, false),
+            basic_recover_ok(1),
+        ];
+
+        assert_round_trip(frames);
+    }
+
+    #[test]
+    fn exchange_and_queue_methods_round_trip() {
+        let frames = vec![
+            exchange_declare_ok(1),
+            exchange_delete(1, "exchange", Some(ExchangeDeleteFlags::IF_UNUSED)),
+            exchange_delete_ok(1),
+            exchange_bind(1, "destination", "source", "rkey"),
+            exchange_bind_ok(1),
+            exchange_unbind(1, "destination", "source", "rkey"),
+            exchange_unbind_ok(1),
+            queue_unbind(1, "queue", "exchange", "rkey"),
+            queue_unbind_ok(1),
+            queue_purge(1, "queue"),
+            queue_purge_ok(1, 12),
+            queue_delete(1, "queue", Some(QueueDeleteFlags::IF_EMPTY | QueueDeleteFlags::NO_WAIT)),
+            queue_delete_ok(1, 3),
+        ];
+
+        assert_round_trip(frames);
+    }
+
+    #[test]
+    fn tx_and_confirm_methods_round_trip() {
+        let frames = vec![
+            confirm_select(1, true),
+            confirm_select_ok(1),
+            tx_select(1),
+            tx_select_ok(1),
+            tx_commit(1),
+            tx_commit_ok(1),
+            tx_rollback(1),
+            tx_rollback_ok(1),
+        ];
+
+        assert_round_trip(frames);
+    }
+
+    #[test]
+    fn connection_secure_blocked_and_update_secret_round_trip() {
+        assert_round_trip(vec![
+            connection_secure(0, b"challenge"),
+            connection_secure_ok(0, b"\x00user\x00\xFF"),
+            connection_blocked(0, "low on memory"),
+            connection_unblocked(0),
+            connection_update_secret(0, b"new token", "token expired"),
+            connection_update_secret_ok(0),
+        ]);
+    }
+
+    #[test]
+    fn frames_are_encoded_in_one_buffer() {
+        let mut codec = AMQPCodec::default();
+        let frames = vec![
+            basic_publish(1, "exchange", "rkey"),
+            AMQPFrame::ContentHeader(content_header(1, 5)),
+            AMQPFrame::ContentBody(content_body(1, b"hello")),
+        ];
+
+        let mut one_by_one = BytesMut::new();
+        for f in frames.clone() {
+            codec.encode(f, &mut one_by_one).unwrap();
+        }
+
+        let mut batch = BytesMut::new();
+        codec.encode(frames, &mut batch).unwrap();
+
+        assert_eq!(batch, one_by_one);
+
+        let mut decoded = 0;
+        while codec.decode(&mut batch).unwrap().is_some() {
+            decoded += 1;
+        }
+
+        assert_eq!(decoded, 3);
+    }
+
+    #[test]
+    fn channel_flow_round_trip() {
+        assert_round_trip(vec![
+            AMQPFrame::Method(1, CHANNEL_FLOW, MethodFrameArgs::ChannelFlow(ChannelFlowArgs { active: true })),
+            AMQPFrame::Method(1, CHANNEL_FLOW_OK, MethodFrameArgs::ChannelFlowOk(ChannelFlowOkArgs { active: false })),
+        ]);
+    }
+
+    #[test]
+    fn reserved_fields_are_encoded_as_zero_values() {
+        let mut buf = BytesMut::new();
+        AMQPCodec::default().encode(connection_open(0, "/"), &mut buf).unwrap();
+
+        // method frame header (7), class method (4), vhost (2), reserved shortstr (1), reserved bit (1)
+        assert_eq!(&buf[11..], &[1, b'/', 0, 0, FRAME_END][..]);
+    }
 }
@@ -0,0 +1,261 @@
+//! Splitting message payloads into content body frames and collecting them back.
+//!
+//! A message is sent as a method frame (like `basic.publish`), a content header and zero or more
+//! content body frames. Body frames cannot be larger than the negotiated frame max, so a big
+//! payload needs to be sent in several frames.
+use crate::frame::{Channel, ContentBodyFrame, ContentHeaderFrame, ReplyCode, FRAME_ERROR, UNEXPECTED_FRAME};
+use crate::{frame_error, Result};
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+
+/// Frame header (7 bytes) and frame end (1 byte) of a content body frame.
+const FRAME_OVERHEAD: usize = 8;
+
+/// A complete message content with its header and the concatenated body.
+#[derive(Clone, Debug)]
+pub struct Content {
+    pub header: ContentHeaderFrame,
+    pub body: Bytes,
+}
+
+#[derive(Debug)]
+struct PartialContent {
+    header: ContentHeaderFrame,
+    body: BytesMut,
+}
+
+/// Split the payload into content body frames which fit in `frame_max`. If `frame_max` is zero
+/// the payload is sent in one frame. Empty payload doesn't need body frames at all.
+///
+/// The frames share the payload buffer, no bytes are copied.
+pub fn content_body_frames(channel: Channel, payload: &Bytes, frame_max: u32) -> Vec<ContentBodyFrame> {
+    let chunk_size = match frame_max as usize {
+        0 => payload.len().max(1),
+        n if n > FRAME_OVERHEAD => n - FRAME_OVERHEAD,
+        _ => 1,
+    };
+
+    (0..payload.len())
+        .step_by(chunk_size)
+        .map(|start| ContentBodyFrame {
+            channel,
+            body: payload.slice(start..payload.len().min(start + chunk_size)),
+        })
+        .collect()
+}
+
+/// Collects content headers and body frames per channel, and gives back the content when all
+/// the `body_size` bytes arrived. If the body arrives in one frame, its buffer is passed on
+/// without copying.
+///
+/// The `body_size` comes from the peer, so the body buffer grows with the arriving frames instead
+/// of being allocated upfront.
+#[derive(Debug, Default)]
+pub struct ContentAssembler {
+    in_progress: HashMap<Channel, PartialContent>,
+    /// Zero means no limit.
+    max_body_size: u64,
+}
+
+impl ContentAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuse the contents which are bigger than `max_body_size`, zero means no limit.
+    pub fn set_max_body_size(&mut self, max_body_size: u64) {
+        self.max_body_size = max_body_size;
+    }
+
+    /// Start collecting a new content. If the body size is zero, the content is complete.
+    pub fn header(&mut self, header: ContentHeaderFrame) -> Result<Option<Content>> {
+        if self.in_progress.contains_key(&header.channel) {
+            return frame_error!(UNEXPECTED_FRAME, "Content header arrived before the previous body is complete");
+        }
+
+        if self.max_body_size > 0 && header.body_size > self.max_body_size {
+            return frame_error!(
+                ReplyCode::ResourceError.into(),
+                format!(
+                    "Body size {} is larger than the maximum message size {}",
+                    header.body_size, self.max_body_size
+                )
+            );
+        }
+
+        if header.body_size == 0 {
+            return Ok(Some(Content {
+                header,
+                body: Bytes::new(),
+            }));
+        }
+
+        self.in_progress.insert(
+            header.channel,
+            PartialContent {
+                header,
+                body: BytesMut::new(),
+            },
+        );
+
+        Ok(None)
+    }
+
+    /// Append the body frame to the content of the channel.
+    pub fn body(&mut self, body: ContentBodyFrame) -> Result<Option<Content>> {
+        let content = match self.in_progress.get_mut(&body.channel) {
+            Some(content) => content,
+            None => return frame_error!(UNEXPECTED_FRAME, "Content body arrived without content header"),
+        };
+
+        let body_size = content.header.body_size;
+
+        if (content.body.len() + body.body.len()) as u64 > body_size {
+            self.in_progress.remove(&body.channel);
+
+            return frame_error!(FRAME_ERROR, "Content body is longer than the body size in the header");
+        }
+
+        if content.body.is_empty() && body.body.len() as u64 == body_size {
+            let content = self.in_progress.remove(&body.channel).unwrap();
+
+            return Ok(Some(Content {
+                header: content.header,
+                body: body.body,
+            }));
+        }
+
+        content.body.extend_from_slice(&body.body);
+
+        if content.body.len() as u64 == body_size {
+            let content = self.in_progress.remove(&body.channel).unwrap();
+
+            Ok(Some(Content {
+                header: content.header,
+                body: content.body.freeze(),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Drop the partially received content of a channel, for example when the channel is closed.
+    pub fn cancel(&mut self, channel: Channel) {
+        self.in_progress.remove(&channel);
+    }
+
+    /// Is there a content being collected on the channel?
+    pub fn is_in_progress(&self, channel: Channel) -> bool {
+        self.in_progress.contains_key(&channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::content_header;
+
+    #[test]
+    fn payload_is_split_by_frame_max() {
+        let payload = Bytes::from(vec![7u8; 300_000]);
+        let frames = content_body_frames(1, &payload, 131_072);
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].body.len(), 131_064);
+        assert_eq!(frames[1].body.len(), 131_064);
+        assert_eq!(frames[2].body.len(), 300_000 - 2 * 131_064);
+    }
+
+    #[test]
+    fn empty_payload_has_no_body_frames() {
+        assert!(content_body_frames(1, &Bytes::new(), 4096).is_empty());
+        assert!(content_body_frames(1, &Bytes::new(), 0).is_empty());
+    }
+
+    #[test]
+    fn zero_frame_max_sends_one_frame() {
+        assert_eq!(content_body_frames(1, &Bytes::from(vec![1u8; 200_000]), 0).len(), 1);
+    }
+
+    #[test]
+    fn split_content_is_reassembled() {
+        let payload = (0..10_000u32).map(|i| i as u8).collect::<Bytes>();
+        let mut assembler = ContentAssembler::new();
+
+        assert!(assembler.header(content_header(2, payload.len() as u64)).unwrap().is_none());
+
+        let mut frames = content_body_frames(2, &payload, 4096);
+        let last = frames.pop().unwrap();
+
+        for f in frames {
+            assert!(assembler.body(f).unwrap().is_none());
+        }
+
+        let content = assembler.body(last).unwrap().unwrap();
+
+        assert_eq!(content.header.channel, 2);
+        assert_eq!(content.body, payload);
+        assert!(!assembler.is_in_progress(2));
+    }
+
+    #[test]
+    fn single_body_frame_is_not_copied() {
+        let payload = Bytes::from(vec![3u8; 1000]);
+        let mut assembler = ContentAssembler::new();
+
+        assembler.header(content_header(1, payload.len() as u64)).unwrap();
+
+        let frame = content_body_frames(1, &payload, 131_072).pop().unwrap();
+        let content = assembler.body(frame).unwrap().unwrap();
+
+        assert_eq!(content.body.as_ptr(), payload.as_ptr());
+    }
+
+    #[test]
+    fn empty_content_is_complete_after_header() {
+        let mut assembler = ContentAssembler::new();
+        let content = assembler.header(content_header(1, 0)).unwrap().unwrap();
+
+        assert!(content.body.is_empty());
+    }
+
+    #[test]
+    fn body_without_header_is_error() {
+        let mut assembler = ContentAssembler::new();
+
+        assert!(assembler.body(crate::frame::content_body(1, b"data")).is_err());
+    }
+
+    #[test]
+    fn huge_body_size_is_not_allocated_upfront() {
+        let mut assembler = ContentAssembler::new();
+
+        assembler.header(content_header(1, u64::MAX)).unwrap();
+        assembler.header(content_header(2, 1 << 40)).unwrap();
+
+        assert!(assembler.body(crate::frame::content_body(1, b"data")).unwrap().is_none());
+        assert!(assembler.body(crate::frame::content_body(2, b"data")).unwrap().is_none());
+    }
+
+    #[test]
+    fn body_size_above_max_is_resource_error() {
+        let mut assembler = ContentAssembler::new();
+        assembler.set_max_body_size(1000);
+
+        assert!(assembler.header(content_header(1, 1000)).unwrap().is_none());
+
+        let err = assembler.header(content_header(2, 1 << 40)).unwrap_err();
+
+        assert_eq!(err.downcast::<crate::FrameError>().unwrap().code, u16::from(ReplyCode::ResourceError));
+        assert!(!assembler.is_in_progress(2));
+    }
+
+    #[test]
+    fn body_longer_than_body_size_is_error() {
+        let mut assembler = ContentAssembler::new();
+        assembler.header(content_header(1, 2)).unwrap();
+
+        assert!(assembler.body(crate::frame::content_body(1, b"data")).is_err());
+        assert!(!assembler.is_in_progress(1));
+    }
+}
@@ -0,0 +1,140 @@
+//! Human readable representation of frames and raw bytes, for debugging what was on the wire.
+//!
+//! The `amqp-dump` binary uses these functions to print a captured AMQP stream.
+use crate::frame::{self, AMQPFrame, MethodFrameArgs};
+use std::fmt::Write;
+
+/// Content body bytes printed at most in a frame dump.
+const BODY_PREVIEW: usize = 64;
+
+/// One line description of the frame with the method name, channel and the fields.
+pub fn format_frame(frame: &AMQPFrame) -> String {
+    match frame {
+        AMQPFrame::Header => "protocol header AMQP 0-9-1".to_string(),
+        AMQPFrame::Method(channel, cm, args) => {
+            let (class_id, method_id) = frame::split_class_method(*cm);
+
+            match frame::method_name(*cm) {
+                Some(name) => format!("channel {} {} {}", channel, name, format_args(args)),
+                None => format!("channel {} unknown method {}.{} {}", channel, class_id, method_id, format_args(args)),
+            }
+        }
+        AMQPFrame::ContentHeader(header) => format!(
+            "channel {} content header class {} body size {} {:?}",
+            header.channel, header.class_id, header.body_size, header.properties
+        ),
+        AMQPFrame::ContentBody(body) => {
+            let preview = &body.body[..body.body.len().min(BODY_PREVIEW)];
+            let ellipsis = if body.body.len() > BODY_PREVIEW { "..." } else { "" };
+
+            format!(
+                "channel {} content body {} bytes \"{}{}\"",
+                body.channel,
+                body.body.len(),
+                printable(preview),
+                ellipsis
+            )
+        }
+        AMQPFrame::Heartbeat(channel) => format!("channel {} heartbeat", channel),
+    }
+}
+
+/// Hex dump with 16 bytes per line, the offset and the printable characters.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+
+    for (i, line) in bytes.chunks(16).enumerate() {
+        write!(out, "{:08X}  ", i * 16).unwrap();
+
+        for b in line {
+            write!(out, "{:02X} ", b).unwrap();
+        }
+
+        for _ in line.len()..16 {
+            out.push_str("   ");
+        }
+
+        writeln!(out, " {}", printable(line)).unwrap();
+    }
+
+    out
+}
+
+/// Parse a hex capture. Whitespace, `0x` prefixes and `:` separators are ignored, so the output
+/// of `xxd -p`, Wireshark raw hex and hex escaped strings can be pasted as is.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits = text
+        .replace("0x", "")
+        .replace("\\x", "")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .collect::<Vec<_>>();
+
+    if digits.len() % 2 != 0 {
+        return Err("Odd number of hex digits".into());
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let s = pair.iter().collect::<String>();
+
+            u8::from_str_radix(&s, 16).map_err(|_| format!("Invalid hex byte {}", s))
+        })
+        .collect()
+}
+
+/// Debug format of the arguments without the enum variant and the struct name.
+fn format_args(args: &MethodFrameArgs) -> String {
+    let text = format!("{:?}", args);
+
+    match (text.find('{'), text.rfind('}')) {
+        (Some(start), Some(end)) => text[start..=end].to_string(),
+        _ => String::new(),
+    }
+}
+
+fn printable(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_frame_is_printed_with_name() {
+        let text = format_frame(&frame::basic_publish(2, "exchg", "rkey"));
+
+        assert!(text.starts_with("channel 2 basic.publish { exchange: \"exchg\""));
+    }
+
+    #[test]
+    fn long_body_is_truncated() {
+        let text = format_frame(&AMQPFrame::ContentBody(frame::content_body(1, &[b'a'; 100])));
+
+        assert!(text.starts_with("channel 1 content body 100 bytes"));
+        assert!(text.ends_with("...\""));
+    }
+
+    #[test]
+    fn hex_with_separators_is_parsed() {
+        assert_eq!(parse_hex("41 4D:51 50\n0x00 \\x00").unwrap(), b"AMQP\x00\x00");
+        assert!(parse_hex("414").is_err());
+        assert!(parse_hex("4G").is_err());
+    }
+
+    #[test]
+    fn hex_dump_has_offsets_and_text() {
+        let dump = hex_dump(b"AMQP\x00\x00\x09\x01AMQP\x00\x00\x09\x01AMQP");
+        let lines = dump.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("00000000  41 4D 51 50 "));
+        assert!(lines[1].starts_with("00000010  41 4D 51 50 "));
+        assert!(lines[1].ends_with(" AMQP"));
+    }
+}
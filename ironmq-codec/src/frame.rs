@@ -1,32 +1,6 @@
+use bytes::Bytes;
 use std::collections::HashMap;
 
-pub const CONNECTION_START: u32 = 0x000A000A;
-pub const CONNECTION_START_OK: u32 = 0x000A000B;
-pub const CONNECTION_TUNE: u32 = 0x000A001E;
-pub const CONNECTION_TUNE_OK: u32 = 0x000A001F;
-pub const CONNECTION_OPEN: u32 = 0x000A0028;
-pub const CONNECTION_OPEN_OK: u32 = 0x000A0029;
-pub const CONNECTION_CLOSE: u32 = 0x000A0032;
-pub const CONNECTION_CLOSE_OK: u32 = 0x000A0033;
-
-pub const CHANNEL_OPEN: u32 = 0x0014000A;
-pub const CHANNEL_OPEN_OK: u32 = 0x0014000B;
-pub const CHANNEL_CLOSE: u32 = 0x00140028;
-pub const CHANNEL_CLOSE_OK: u32 = 0x00140029;
-
-pub const EXCHANGE_DECLARE: u32 = 0x0028000A;
-pub const EXCHANGE_DECLARE_OK: u32 = 0x0028000B;
-
-pub const QUEUE_DECLARE: u32 = 0x0032000A;
-pub const QUEUE_DECLARE_OK: u32 = 0x0032000B;
-pub const QUEUE_BIND: u32 = 0x00320014;
-pub const QUEUE_BIND_OK: u32 = 0x00320015;
-
-pub const BASIC_CONSUME: u32 = 0x003C0014;
-pub const BASIC_CONSUME_OK: u32 = 0x003C0015;
-pub const BASIC_PUBLISH: u32 = 0x003C0028;
-pub const BASIC_DELIVER: u32 = 0x003C003C;
-
 pub type Channel = u16;
 pub type ClassMethod = u32;
 pub type ClassId = u16;
@@ -34,6 +8,7 @@ pub type Weight = u16;
 
 /// Represents an AMQP frame.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AMQPFrame {
     /// Header is to be sent to the server at first, announcing the AMQP version we support
     Header,
@@ -52,7 +27,10 @@ impl std::fmt::Debug for AMQPFrame {
             AMQPFrame::Header =>
                 write!(f, "Header"),
             AMQPFrame::Method(ch, cm, args) =>
-                write!(f, "Method(channel={}, {:08X}, {:?})", ch, cm, args),
+                match method_name(*cm) {
+                    Some(name) => write!(f, "Method(channel={}, {}, {:?})", ch, name, args),
+                    None => write!(f, "Method(channel={}, {:08X}, {:?})", ch, cm, args),
+                },
             AMQPFrame::ContentHeader(ch) =>
                 write!(f, "ContentHeader({:?})", ch),
             AMQPFrame::ContentBody(cb) =>
@@ -63,58 +41,101 @@ impl std::fmt::Debug for AMQPFrame {
     }
 }
 
-/// Represents all types of method frame arguments.
-#[derive(Clone, Debug)]
-pub enum MethodFrameArgs {
-    ConnectionStart(ConnectionStartArgs),
-    ConnectionStartOk(ConnectionStartOkArgs),
-    ConnectionTune(ConnectionTuneArgs),
-    ConnectionTuneOk(ConnectionTuneOkArgs),
-    ConnectionOpen(ConnectionOpenArgs),
-    ConnectionOpenOk,
-    ConnectionClose(ConnectionCloseArgs),
-    ConnectionCloseOk,
-    ChannelOpen,
-    ChannelOpenOk,
-    ChannelClose(ChannelCloseArgs),
-    ChannelCloseOk,
-    ExchangeDeclare(ExchangeDeclareArgs),
-    ExchangeDeclareOk,
-    ExchangeBind(ExchangeBindArgs),
-    ExchangeBindOk,
-    QueueDeclare(QueueDeclareArgs),
-    QueueDeclareOk(QueueDeclareOkArgs),
-    QueueBind(QueueBindArgs),
-    QueueBindOk,
-    BasicConsume(BasicConsumeArgs),
-    BasicConsumeOk(BasicConsumeOkArgs),
-    BasicDeliver(BasicDeliverArgs),
-    BasicPublish(BasicPublishArgs)
-}
-
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ContentHeaderFrame {
     pub channel: Channel,
     pub class_id: ClassId,
     pub weight: Weight,
     pub body_size: u64,
-    pub prop_flags: u16, // we need to elaborate the properties here
-    pub args: Vec<AMQPValue>,
+    /// The property flags are calculated from the properties which are set during encoding.
+    pub properties: BasicProperties,
+}
+
+bitflags! {
+    /// Property flags of the content header, the first property is the most significant bit.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "serde", serde(transparent))]
+    pub struct HeaderPropertyFlags: u16 {
+        const CONTENT_TYPE = 0x8000;
+        const CONTENT_ENCODING = 0x4000;
+        const HEADERS = 0x2000;
+        const DELIVERY_MODE = 0x1000;
+        const PRIORITY = 0x0800;
+        const CORRELATION_ID = 0x0400;
+        const REPLY_TO = 0x0200;
+        const EXPIRATION = 0x0100;
+        const MESSAGE_ID = 0x0080;
+        const TIMESTAMP = 0x0040;
+        const MESSAGE_TYPE = 0x0020;
+        const USER_ID = 0x0010;
+        const APP_ID = 0x0008;
+        const CLUSTER_ID = 0x0004;
+        /// There is another property flags word after this one.
+        const CONTINUATION = 0x0001;
+    }
+}
+
+impl Default for HeaderPropertyFlags {
+    fn default() -> Self {
+        HeaderPropertyFlags::empty()
+    }
+}
+
+/// Properties of the basic class content. Only those properties are sent which are set.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BasicProperties {
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub headers: Option<FieldTable>,
+    /// 1 - non-persistent, 2 - persistent
+    pub delivery_mode: Option<u8>,
+    pub priority: Option<u8>,
+    pub correlation_id: Option<String>,
+    pub reply_to: Option<String>,
+    pub expiration: Option<String>,
+    pub message_id: Option<String>,
+    pub timestamp: Option<u64>,
+    pub message_type: Option<String>,
+    pub user_id: Option<String>,
+    pub app_id: Option<String>,
+}
+
+impl BasicProperties {
+    /// Property flags representing which properties are set.
+    pub fn flags(&self) -> HeaderPropertyFlags {
+        let mut flags = HeaderPropertyFlags::empty();
+
+        flags.set(HeaderPropertyFlags::CONTENT_TYPE, self.content_type.is_some());
+        flags.set(HeaderPropertyFlags::CONTENT_ENCODING, self.content_encoding.is_some());
+        flags.set(HeaderPropertyFlags::HEADERS, self.headers.is_some());
+        flags.set(HeaderPropertyFlags::DELIVERY_MODE, self.delivery_mode.is_some());
+        flags.set(HeaderPropertyFlags::PRIORITY, self.priority.is_some());
+        flags.set(HeaderPropertyFlags::CORRELATION_ID, self.correlation_id.is_some());
+        flags.set(HeaderPropertyFlags::REPLY_TO, self.reply_to.is_some());
+        flags.set(HeaderPropertyFlags::EXPIRATION, self.expiration.is_some());
+        flags.set(HeaderPropertyFlags::MESSAGE_ID, self.message_id.is_some());
+        flags.set(HeaderPropertyFlags::TIMESTAMP, self.timestamp.is_some());
+        flags.set(HeaderPropertyFlags::MESSAGE_TYPE, self.message_type.is_some());
+        flags.set(HeaderPropertyFlags::USER_ID, self.user_id.is_some());
+        flags.set(HeaderPropertyFlags::APP_ID, self.app_id.is_some());
+
+        flags
+    }
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ContentBodyFrame {
     pub channel: Channel,
-    // TODO here we need something which can be cloned cheap like Box or Rc. Sometimes we can move
-    // out this from the struct and we can build a new struct. But we need to avoid the
-    // byte-to-byte copy.
-    pub body: Vec<u8>,
+    /// Reference counted payload, cloning the frame doesn't copy the body bytes.
+    pub body: Bytes,
 }
 
-/// Type alias for inner type of field value.
-pub type FieldTable = HashMap<String, AMQPFieldValue>;
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AMQPValue {
     //    Bool(bool),
     U8(u8),
@@ -127,199 +148,252 @@ pub enum AMQPValue {
     FieldTable(Box<FieldTable>),
 }
 
-#[derive(Clone, Debug)]
+/// Values of field tables and field arrays. The type tags follow the RabbitMQ errata of the
+/// AMQP 0-9-1 spec, since this is what the most of the clients use.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AMQPFieldValue {
+    /// `t` boolean
     Bool(bool),
-    //    SimpleString(String),
+    /// `b` signed 8-bit integer
+    I8(i8),
+    /// `B` unsigned 8-bit integer
+    U8(u8),
+    /// `s` signed 16-bit integer
+    I16(i16),
+    /// `u` unsigned 16-bit integer
+    U16(u16),
+    /// `I` signed 32-bit integer
+    I32(i32),
+    /// `i` unsigned 32-bit integer
+    U32(u32),
+    /// `l` signed 64-bit integer
+    I64(i64),
+    /// `f` 32-bit float
+    F32(f32),
+    /// `d` 64-bit float
+    F64(f64),
+    /// `D` decimal value, the number of decimal digits (scale) and the unscaled value
+    Decimal(u8, u32),
+    /// `S` long string
     LongString(String),
+    /// `x` byte array
+    ByteArray(Vec<u8>),
+    /// `A` field array, a list of field values without names
+    Array(Vec<AMQPFieldValue>),
+    /// `T` timestamp, seconds since the epoch
+    Timestamp(u64),
     EmptyFieldTable,
+    /// `F` nested field table
     FieldTable(Box<FieldTable>),
+    /// `V` no value
+    Void,
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct ConnectionStartArgs {
-    pub version_major: u8,
-    pub version_minor: u8,
-    pub capabilities: Option<FieldTable>,
-    pub properties: Option<FieldTable>,
-    pub mechanisms: String,
-    pub locales: String,
+/// Field table which keeps the insertion order of the fields, so encoding it gives the same bytes
+/// every time. Tables are small, so the fields are looked up linearly. Collecting a table from an
+/// iterator, as the decoder does, finds the duplicated names by a hash map, so a table with many
+/// fields from the peer doesn't take quadratic time.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct FieldTable {
+    fields: Vec<(String, AMQPFieldValue)>,
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct ConnectionStartOkArgs {
-    pub capabilities: Option<FieldTable>,
-    pub properties: Option<FieldTable>,
-    pub mechanism: String,
-    pub response: String,
-    pub locale: String,
-}
+static EMPTY_FIELD_TABLE: FieldTable = FieldTable::new();
 
-#[derive(Clone, Debug, Default)]
-pub struct ConnectionTuneArgs {
-    pub channel_max: u16,
-    pub frame_max: u32,
-    pub heartbeat: u16,
-}
+impl FieldTable {
+    pub const fn new() -> FieldTable {
+        FieldTable { fields: Vec::new() }
+    }
 
-#[derive(Clone, Debug, Default)]
-pub struct ConnectionTuneOkArgs {
-    pub channel_max: u16,
-    pub frame_max: u32,
-    pub heartbeat: u16,
-}
+    pub fn builder() -> FieldTableBuilder {
+        FieldTableBuilder::default()
+    }
 
-#[derive(Clone, Debug, Default)]
-pub struct ConnectionOpenArgs {
-    pub virtual_host: String,
-    pub insist: bool,
-}
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
 
-#[derive(Clone, Debug, Default)]
-pub struct ConnectionCloseArgs {
-    pub code: u16,
-    pub text: String,
-    pub class_id: u16,
-    pub method_id: u16,
-}
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
 
-#[derive(Clone, Debug, Default)]
-pub struct ChannelCloseArgs {
-    pub code: u16,
-    pub text: String,
-    pub class_id: u16,
-    pub method_id: u16,
-}
+    /// Insert a field to the end of the table. If the field already exists its value is replaced
+    /// in place and the old value is given back.
+    pub fn insert(&mut self, name: String, value: AMQPFieldValue) -> Option<AMQPFieldValue> {
+        match self.fields.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => Some(std::mem::replace(v, value)),
+            None => {
+                self.fields.push((name, value));
+                None
+            }
+        }
+    }
 
-bitflags! {
-    pub struct ExchangeDeclareFlags: u8 {
-        const PASSIVE = 0b00000001;
-        const DURABLE = 0b00000010;
-        const AUTO_DELETE = 0b00000100;
-        const INTERNAL = 0b00001000;
-        const NO_WAIT = 0b00010000;
+    pub fn remove(&mut self, name: &str) -> Option<AMQPFieldValue> {
+        let pos = self.fields.iter().position(|(n, _)| n == name)?;
+
+        Some(self.fields.remove(pos).1)
     }
-}
 
-impl Default for ExchangeDeclareFlags {
-    fn default() -> Self {
-        ExchangeDeclareFlags::empty()
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
     }
-}
 
-#[derive(Clone, Debug, Default)]
-pub struct ExchangeDeclareArgs {
-    pub exchange_name: String,
-    pub exchange_type: String,
-    pub flags: ExchangeDeclareFlags,
-    pub args: Option<FieldTable>,
-}
+    pub fn get(&self, name: &str) -> Option<&AMQPFieldValue> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
 
-#[derive(Clone, Debug, Default)]
-pub struct ExchangeBindArgs {
-    pub source: String,
-    pub destination: String,
-    pub routing_key: String,
-    pub no_wait: bool,
-    pub args: Option<FieldTable>,
-}
+    /// Iterate over the fields in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &AMQPFieldValue)> {
+        self.fields.iter().map(|(n, v)| (n, v))
+    }
 
-bitflags! {
-    pub struct QueueDeclareFlags: u8 {
-        const PASSIVE = 0b00000001;
-        const DURABLE = 0b00000010;
-        const EXCLUSIVE = 0b00000100;
-        const AUTO_DELETE = 0b00001000;
-        const NO_WAIT = 0b00010000;
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            AMQPFieldValue::Bool(v) => Some(*v),
+            _ => None,
+        }
     }
-}
 
-impl Default for QueueDeclareFlags {
-    fn default() -> Self {
-        QueueDeclareFlags::empty()
+    /// Integer value of any integer type which fits in an `i64`, timestamps included.
+    pub fn get_i64(&self, name: &str) -> Option<i64> {
+        match *self.get(name)? {
+            AMQPFieldValue::I8(v) => Some(v as i64),
+            AMQPFieldValue::U8(v) => Some(v as i64),
+            AMQPFieldValue::I16(v) => Some(v as i64),
+            AMQPFieldValue::U16(v) => Some(v as i64),
+            AMQPFieldValue::I32(v) => Some(v as i64),
+            AMQPFieldValue::U32(v) => Some(v as i64),
+            AMQPFieldValue::I64(v) => Some(v),
+            AMQPFieldValue::Timestamp(v) => std::convert::TryFrom::try_from(v).ok(),
+            _ => None,
+        }
     }
-}
 
-#[derive(Clone, Debug, Default)]
-pub struct QueueDeclareArgs {
-    pub name: String,
-    pub flags: QueueDeclareFlags,
-    pub args: Option<FieldTable>,
-}
+    /// Float value, integers are not converted.
+    pub fn get_f64(&self, name: &str) -> Option<f64> {
+        match *self.get(name)? {
+            AMQPFieldValue::F32(v) => Some(v as f64),
+            AMQPFieldValue::F64(v) => Some(v),
+            _ => None,
+        }
+    }
 
-#[derive(Clone, Debug, Default)]
-pub struct QueueDeclareOkArgs {
-    pub name: String,
-    pub message_count: u32,
-    pub consumer_count: u32,
-}
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            AMQPFieldValue::LongString(v) => Some(v),
+            _ => None,
+        }
+    }
 
-#[derive(Clone, Debug, Default)]
-pub struct QueueBindArgs {
-    pub queue_name: String,
-    pub exchange_name: String,
-    pub routing_key: String,
-    pub no_wait: bool,
-    pub args: Option<FieldTable>,
-}
+    pub fn get_bytes(&self, name: &str) -> Option<&[u8]> {
+        match self.get(name)? {
+            AMQPFieldValue::ByteArray(v) => Some(v),
+            _ => None,
+        }
+    }
 
-bitflags! {
-    pub struct BasicConsumeFlags: u8 {
-        const NO_LOCAL = 0b00000001;
-        const NO_ACK = 0b00000010;
-        const EXCLUSIVE = 0b00000100;
-        const NO_WAIT = 0b00001000;
+    pub fn get_array(&self, name: &str) -> Option<&[AMQPFieldValue]> {
+        match self.get(name)? {
+            AMQPFieldValue::Array(v) => Some(v),
+            _ => None,
+        }
     }
-}
 
-impl Default for BasicConsumeFlags {
-    fn default() -> Self {
-        BasicConsumeFlags::NO_ACK
+    /// Nested field table, an empty nested table is given back as an empty `FieldTable`.
+    pub fn get_table(&self, name: &str) -> Option<&FieldTable> {
+        match self.get(name)? {
+            AMQPFieldValue::FieldTable(v) => Some(v),
+            AMQPFieldValue::EmptyFieldTable => Some(&EMPTY_FIELD_TABLE),
+            _ => None,
+        }
     }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct BasicConsumeArgs {
-    pub queue: String,
-    pub consumer_tag: String,
-    pub flags: BasicConsumeFlags,
-    pub args: Option<FieldTable>,
-}
+impl<'a> IntoIterator for &'a FieldTable {
+    type Item = (&'a String, &'a AMQPFieldValue);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
 
-#[derive(Clone, Debug, Default)]
-pub struct BasicConsumeOkArgs {
-    pub consumer_tag: String,
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct BasicDeliverArgs {
-    pub consumer_tag: String,
-    pub delivery_tag: u64,
-    pub redelivered: bool,
-    pub exchange_name: String,
-    pub routing_key: String,
-}
+impl std::iter::FromIterator<(String, AMQPFieldValue)> for FieldTable {
+    /// Duplicated fields behave like `insert`, the field keeps its first position and gets the
+    /// last value.
+    fn from_iter<I: IntoIterator<Item = (String, AMQPFieldValue)>>(iter: I) -> FieldTable {
+        let mut fields: Vec<(String, AMQPFieldValue)> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+
+        for (name, value) in iter {
+            match positions.get(&name) {
+                Some(&pos) => fields[pos].1 = value,
+                None => {
+                    positions.insert(name.clone(), fields.len());
+                    fields.push((name, value));
+                }
+            }
+        }
 
-bitflags! {
-    pub struct BasicPublishFlags: u8 {
-        const MANDATORY = 0b00000001;
-        const IMMEDIATE = 0b00000010;
+        FieldTable { fields }
     }
 }
 
-impl Default for BasicPublishFlags {
-    fn default() -> Self {
-        BasicPublishFlags::empty()
+/// Builds a field table with typed values.
+///
+/// ```
+/// use ironmq_codec::frame::FieldTable;
+///
+/// let args = FieldTable::builder()
+///     .i32("x-message-ttl", 60_000)
+///     .str("x-dead-letter-exchange", "dlx")
+///     .build();
+///
+/// assert_eq!(args.get_i64("x-message-ttl"), Some(60_000));
+/// ```
+#[derive(Debug, Default)]
+pub struct FieldTableBuilder {
+    table: FieldTable,
+}
+
+impl FieldTableBuilder {
+    pub fn value(mut self, name: &str, value: AMQPFieldValue) -> FieldTableBuilder {
+        self.table.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn bool(self, name: &str, value: bool) -> FieldTableBuilder {
+        self.value(name, AMQPFieldValue::Bool(value))
+    }
+
+    pub fn i32(self, name: &str, value: i32) -> FieldTableBuilder {
+        self.value(name, AMQPFieldValue::I32(value))
+    }
+
+    pub fn i64(self, name: &str, value: i64) -> FieldTableBuilder {
+        self.value(name, AMQPFieldValue::I64(value))
+    }
+
+    pub fn str(self, name: &str, value: &str) -> FieldTableBuilder {
+        self.value(name, AMQPFieldValue::LongString(value.to_string()))
+    }
+
+    pub fn table(self, name: &str, value: FieldTable) -> FieldTableBuilder {
+        self.value(name, AMQPFieldValue::FieldTable(Box::new(value)))
     }
-}
 
-#[derive(Clone, Debug, Default)]
-pub struct BasicPublishArgs {
-    pub exchange_name: String,
-    pub routing_key: String,
-    pub flags: BasicPublishFlags
+    pub fn build(self) -> FieldTable {
+        self.table
+    }
 }
 
+// Constants, method arguments and `MethodFrameArgs` generated from the AMQP 0-9-1 spec.
+include!(concat!(env!("OUT_DIR"), "/frame_methods.rs"));
+
 impl From<ContentHeaderFrame> for AMQPFrame {
     fn from(chf: ContentHeaderFrame) -> AMQPFrame {
         AMQPFrame::ContentHeader(chf)
@@ -370,9 +444,8 @@ pub fn connection_start(channel: u16) -> AMQPFrame {
         MethodFrameArgs::ConnectionStart(ConnectionStartArgs {
             version_major: 0,
             version_minor: 9,
-            capabilities: None,
-            properties: Some(server_properties),
-            mechanisms: "PLAIN".into(),
+            server_properties: Some(server_properties),
+            mechanisms: "PLAIN AMQPLAIN".into(),
             locales: "en_US".into()
         }))
 }
@@ -393,39 +466,56 @@ pub fn connection_start_ok(username: &str, password: &str, capabilities: FieldTa
     auth.push(0x00);
     auth.extend_from_slice(password.as_bytes());
 
-    let auth_string = String::from_utf8(auth).unwrap();
-
     AMQPFrame::Method(
         0,
         CONNECTION_START_OK,
         MethodFrameArgs::ConnectionStartOk(ConnectionStartOkArgs {
-            capabilities: None,
-            properties: Some(client_properties),
+            client_properties: Some(client_properties),
             mechanism: "PLAIN".into(),
-            response: auth_string,
+            response: auth.into(),
             locale: "en_US".into()
         }))
 }
 
-pub fn connection_tune(channel: u16) -> AMQPFrame {
+pub fn connection_secure(channel: u16, challenge: &[u8]) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        CONNECTION_SECURE,
+        MethodFrameArgs::ConnectionSecure(ConnectionSecureArgs {
+            challenge: Bytes::copy_from_slice(challenge),
+        }),
+    )
+}
+
+pub fn connection_secure_ok(channel: u16, response: &[u8]) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        CONNECTION_SECURE_OK,
+        MethodFrameArgs::ConnectionSecureOk(ConnectionSecureOkArgs {
+            response: Bytes::copy_from_slice(response),
+        }),
+    )
+}
+
+pub fn connection_tune(channel: u16, channel_max: u16, frame_max: u32, heartbeat: u16) -> AMQPFrame {
     AMQPFrame::Method(
         channel,
         CONNECTION_TUNE,
         MethodFrameArgs::ConnectionTune(ConnectionTuneArgs {
-            channel_max: 2047,
-            frame_max: 131_072,
-            heartbeat: 60
+            channel_max,
+            frame_max,
+            heartbeat
         }))
 }
 
-pub fn connection_tune_ok(channel: u16) -> AMQPFrame {
+pub fn connection_tune_ok(channel: u16, channel_max: u16, frame_max: u32, heartbeat: u16) -> AMQPFrame {
     AMQPFrame::Method(
         channel,
         CONNECTION_TUNE_OK,
         MethodFrameArgs::ConnectionTuneOk(ConnectionTuneOkArgs {
-            channel_max: 2047,
-            frame_max: 131_072,
-            heartbeat: 60
+            channel_max,
+            frame_max,
+            heartbeat
         }))
 }
 
@@ -434,8 +524,7 @@ pub fn connection_open(channel: u16, virtual_host: &str) -> AMQPFrame {
         channel,
         CONNECTION_OPEN,
         MethodFrameArgs::ConnectionOpen(ConnectionOpenArgs {
-            virtual_host: virtual_host.to_string(),
-            insist: true
+            virtual_host: virtual_host.to_string()
         }))
 }
 
@@ -452,8 +541,8 @@ pub fn connection_close(channel: u16, code: u16, text: &str, class_id: u16, meth
         channel,
         CONNECTION_CLOSE,
         MethodFrameArgs::ConnectionClose(ConnectionCloseArgs {
-            code: code,
-            text: text.into(),
+            reply_code: code,
+            reply_text: text.into(),
             class_id: class_id,
             method_id: method_id
         }))
@@ -467,6 +556,35 @@ pub fn connection_close_ok(channel: u16) -> AMQPFrame {
         )
 }
 
+pub fn connection_blocked(channel: u16, reason: &str) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        CONNECTION_BLOCKED,
+        MethodFrameArgs::ConnectionBlocked(ConnectionBlockedArgs {
+            reason: reason.to_string(),
+        }),
+    )
+}
+
+pub fn connection_unblocked(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, CONNECTION_UNBLOCKED, MethodFrameArgs::ConnectionUnblocked)
+}
+
+pub fn connection_update_secret(channel: u16, new_secret: &[u8], reason: &str) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        CONNECTION_UPDATE_SECRET,
+        MethodFrameArgs::ConnectionUpdateSecret(ConnectionUpdateSecretArgs {
+            new_secret: Bytes::copy_from_slice(new_secret),
+            reason: reason.to_string(),
+        }),
+    )
+}
+
+pub fn connection_update_secret_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, CONNECTION_UPDATE_SECRET_OK, MethodFrameArgs::ConnectionUpdateSecretOk)
+}
+
 pub fn channel_open(channel: u16) -> AMQPFrame {
     AMQPFrame::Method(
         channel,
@@ -488,8 +606,8 @@ pub fn channel_close(channel: Channel, code: u16, text: &str, class_id: u16, met
         channel,
         CHANNEL_CLOSE,
         MethodFrameArgs::ChannelClose(ChannelCloseArgs {
-            code: code,
-            text: text.into(),
+            reply_code: code,
+            reply_text: text.into(),
             class_id: class_id,
             method_id: method_id
         }))
@@ -505,10 +623,10 @@ pub fn exchange_declare(channel: u16, exchange_name: &str, exchange_type: &str,
         channel,
         EXCHANGE_DECLARE,
         MethodFrameArgs::ExchangeDeclare(ExchangeDeclareArgs {
-            exchange_name: exchange_name.to_string(),
+            exchange: exchange_name.to_string(),
             exchange_type: exchange_type.to_string(),
             flags: flags.unwrap_or_default(),
-            args: None
+            arguments: None
         }))
 }
 
@@ -520,16 +638,67 @@ pub fn exchange_declare_ok(channel: u16) -> AMQPFrame {
     )
 }
 
+pub fn exchange_delete(channel: u16, exchange_name: &str, flags: Option<ExchangeDeleteFlags>) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        EXCHANGE_DELETE,
+        MethodFrameArgs::ExchangeDelete(ExchangeDeleteArgs {
+            exchange: exchange_name.to_string(),
+            flags: flags.unwrap_or_default(),
+        }),
+    )
+}
+
+pub fn exchange_delete_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, EXCHANGE_DELETE_OK, MethodFrameArgs::ExchangeDeleteOk)
+}
+
+pub fn exchange_bind(channel: u16, destination: &str, source: &str, routing_key: &str) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        EXCHANGE_BIND,
+        MethodFrameArgs::ExchangeBind(ExchangeBindArgs {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            routing_key: routing_key.to_string(),
+            no_wait: false,
+            arguments: None,
+        }),
+    )
+}
+
+pub fn exchange_bind_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, EXCHANGE_BIND_OK, MethodFrameArgs::ExchangeBindOk)
+}
+
+pub fn exchange_unbind(channel: u16, destination: &str, source: &str, routing_key: &str) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        EXCHANGE_UNBIND,
+        MethodFrameArgs::ExchangeUnbind(ExchangeUnbindArgs {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            routing_key: routing_key.to_string(),
+            no_wait: false,
+            arguments: None,
+        }),
+    )
+}
+
+pub fn exchange_unbind_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, EXCHANGE_UNBIND_OK, MethodFrameArgs::ExchangeUnbindOk)
+}
+
 pub fn queue_bind(channel: u16, queue_name: &str, exchange_name: &str, routing_key: &str) -> AMQPFrame {
     AMQPFrame::Method(
         channel,
         QUEUE_BIND,
         MethodFrameArgs::QueueBind(QueueBindArgs {
-            queue_name: queue_name.to_string(),
-            exchange_name: exchange_name.to_string(),
+            queue: queue_name.to_string(),
+            exchange: exchange_name.to_string(),
             routing_key: routing_key.to_string(),
             no_wait: false,
-            args: None
+            arguments: None
         }))
 }
 
@@ -541,14 +710,69 @@ pub fn queue_bind_ok(channel: u16) -> AMQPFrame {
     )
 }
 
+pub fn queue_unbind(channel: u16, queue_name: &str, exchange_name: &str, routing_key: &str) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        QUEUE_UNBIND,
+        MethodFrameArgs::QueueUnbind(QueueUnbindArgs {
+            queue: queue_name.to_string(),
+            exchange: exchange_name.to_string(),
+            routing_key: routing_key.to_string(),
+            arguments: None,
+        }),
+    )
+}
+
+pub fn queue_unbind_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, QUEUE_UNBIND_OK, MethodFrameArgs::QueueUnbindOk)
+}
+
+pub fn queue_purge(channel: u16, queue_name: &str) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        QUEUE_PURGE,
+        MethodFrameArgs::QueuePurge(QueuePurgeArgs {
+            queue: queue_name.to_string(),
+            no_wait: false,
+        }),
+    )
+}
+
+pub fn queue_purge_ok(channel: u16, message_count: u32) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        QUEUE_PURGE_OK,
+        MethodFrameArgs::QueuePurgeOk(QueuePurgeOkArgs { message_count }),
+    )
+}
+
+pub fn queue_delete(channel: u16, queue_name: &str, flags: Option<QueueDeleteFlags>) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        QUEUE_DELETE,
+        MethodFrameArgs::QueueDelete(QueueDeleteArgs {
+            queue: queue_name.to_string(),
+            flags: flags.unwrap_or_default(),
+        }),
+    )
+}
+
+pub fn queue_delete_ok(channel: u16, message_count: u32) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        QUEUE_DELETE_OK,
+        MethodFrameArgs::QueueDeleteOk(QueueDeleteOkArgs { message_count }),
+    )
+}
+
 pub fn queue_declare(channel: u16, queue_name: &str) -> AMQPFrame {
     AMQPFrame::Method(
         channel,
         QUEUE_DECLARE,
         MethodFrameArgs::QueueDeclare(QueueDeclareArgs {
-            name: queue_name.to_string(),
+            queue: queue_name.to_string(),
             flags: QueueDeclareFlags::empty(),
-            args: None
+            arguments: None
         }))
 }
 
@@ -557,7 +781,7 @@ pub fn queue_declare_ok(channel: u16, queue_name: String, message_count: u32, co
         channel,
         QUEUE_DECLARE_OK,
         MethodFrameArgs::QueueDeclareOk(QueueDeclareOkArgs {
-            name: queue_name,
+            queue: queue_name,
             message_count: message_count,
             consumer_count: consumer_count
         }))
@@ -570,8 +794,8 @@ pub fn basic_consume(channel: u16, queue_name: &str, consumer_tag: &str) -> AMQP
         MethodFrameArgs::BasicConsume(BasicConsumeArgs {
             queue: queue_name.to_string(),
             consumer_tag: consumer_tag.to_string(),
-            flags: BasicConsumeFlags::default(),
-            args: None
+            flags: BasicConsumeFlags::NO_ACK,
+            arguments: None
         }))
 }
 
@@ -593,7 +817,7 @@ pub fn basic_deliver(channel: u16, consumer_tag: &str, delivery_tag: u64, redeli
             consumer_tag: consumer_tag.to_string(),
             delivery_tag: delivery_tag,
             redelivered: redelivered,
-            exchange_name: exchange_name.to_string(),
+            exchange: exchange_name.to_string(),
             routing_key: routing_key.to_string()
         })
     )
@@ -604,27 +828,196 @@ pub fn basic_publish(channel: u16, exchange_name: &str, routing_key: &str) -> AM
         channel,
         BASIC_PUBLISH,
         MethodFrameArgs::BasicPublish(BasicPublishArgs {
-            exchange_name: exchange_name.to_string(),
+            exchange: exchange_name.to_string(),
             routing_key: routing_key.to_string(),
             flags: BasicPublishFlags::empty()
         })
     )
 }
 
+pub fn basic_qos(channel: u16, prefetch_size: u32, prefetch_count: u16, global: bool) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_QOS,
+        MethodFrameArgs::BasicQos(BasicQosArgs {
+            prefetch_size,
+            prefetch_count,
+            global,
+        }),
+    )
+}
+
+pub fn basic_qos_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, BASIC_QOS_OK, MethodFrameArgs::BasicQosOk)
+}
+
+pub fn basic_cancel(channel: u16, consumer_tag: &str, no_wait: bool) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_CANCEL,
+        MethodFrameArgs::BasicCancel(BasicCancelArgs {
+            consumer_tag: consumer_tag.to_string(),
+            no_wait,
+        }),
+    )
+}
+
+pub fn basic_cancel_ok(channel: u16, consumer_tag: &str) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_CANCEL_OK,
+        MethodFrameArgs::BasicCancelOk(BasicCancelOkArgs {
+            consumer_tag: consumer_tag.to_string(),
+        }),
+    )
+}
+
+pub fn basic_return(channel: u16, reply_code: u16, reply_text: &str, exchange_name: &str,
+                    routing_key: &str) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_RETURN,
+        MethodFrameArgs::BasicReturn(BasicReturnArgs {
+            reply_code,
+            reply_text: reply_text.to_string(),
+            exchange: exchange_name.to_string(),
+            routing_key: routing_key.to_string(),
+        }),
+    )
+}
+
+pub fn basic_get(channel: u16, queue_name: &str, no_ack: bool) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_GET,
+        MethodFrameArgs::BasicGet(BasicGetArgs {
+            queue: queue_name.to_string(),
+            no_ack,
+        }),
+    )
+}
+
+pub fn basic_get_ok(channel: u16, delivery_tag: u64, redelivered: bool, exchange_name: &str,
+                    routing_key: &str, message_count: u32) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_GET_OK,
+        MethodFrameArgs::BasicGetOk(BasicGetOkArgs {
+            delivery_tag,
+            redelivered,
+            exchange: exchange_name.to_string(),
+            routing_key: routing_key.to_string(),
+            message_count,
+        }),
+    )
+}
+
+pub fn basic_get_empty(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, BASIC_GET_EMPTY, MethodFrameArgs::BasicGetEmpty)
+}
+
+pub fn basic_ack(channel: u16, delivery_tag: u64, multiple: bool) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_ACK,
+        MethodFrameArgs::BasicAck(BasicAckArgs { delivery_tag, multiple }),
+    )
+}
+
+pub fn basic_reject(channel: u16, delivery_tag: u64, requeue: bool) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_REJECT,
+        MethodFrameArgs::BasicReject(BasicRejectArgs { delivery_tag, requeue }),
+    )
+}
+
+pub fn basic_nack(channel: u16, delivery_tag: u64, flags: Option<BasicNackFlags>) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_NACK,
+        MethodFrameArgs::BasicNack(BasicNackArgs {
+            delivery_tag,
+            flags: flags.unwrap_or(BasicNackFlags::REQUEUE),
+        }),
+    )
+}
+
+pub fn basic_recover(channel: u16, requeue: bool) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_RECOVER,
+        MethodFrameArgs::BasicRecover(BasicRecoverArgs { requeue }),
+    )
+}
+
+pub fn basic_recover_async(channel: u16, requeue: bool) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        BASIC_RECOVER_ASYNC,
+        MethodFrameArgs::BasicRecoverAsync(BasicRecoverAsyncArgs { requeue }),
+    )
+}
+
+pub fn basic_recover_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, BASIC_RECOVER_OK, MethodFrameArgs::BasicRecoverOk)
+}
+
+pub fn confirm_select(channel: u16, no_wait: bool) -> AMQPFrame {
+    AMQPFrame::Method(
+        channel,
+        CONFIRM_SELECT,
+        MethodFrameArgs::ConfirmSelect(ConfirmSelectArgs { nowait: no_wait }),
+    )
+}
+
+pub fn confirm_select_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, CONFIRM_SELECT_OK, MethodFrameArgs::ConfirmSelectOk)
+}
+
+pub fn tx_select(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, TX_SELECT, MethodFrameArgs::TxSelect)
+}
+
+pub fn tx_select_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, TX_SELECT_OK, MethodFrameArgs::TxSelectOk)
+}
+
+pub fn tx_commit(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, TX_COMMIT, MethodFrameArgs::TxCommit)
+}
+
+pub fn tx_commit_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, TX_COMMIT_OK, MethodFrameArgs::TxCommitOk)
+}
+
+pub fn tx_rollback(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, TX_ROLLBACK, MethodFrameArgs::TxRollback)
+}
+
+pub fn tx_rollback_ok(channel: u16) -> AMQPFrame {
+    AMQPFrame::Method(channel, TX_ROLLBACK_OK, MethodFrameArgs::TxRollbackOk)
+}
+
 pub fn content_header(channel: u16, size: u64) -> ContentHeaderFrame {
+    content_header_with_properties(channel, size, BasicProperties::default())
+}
+
+pub fn content_header_with_properties(channel: u16, size: u64, properties: BasicProperties) -> ContentHeaderFrame {
     ContentHeaderFrame {
-        channel: channel,
+        channel,
         class_id: 0x003C,
         weight: 0,
         body_size: size,
-        prop_flags: 0x0000,
-        args: vec![],
+        properties,
     }
 }
 
 pub fn content_body(channel: u16, payload: &[u8]) -> ContentBodyFrame {
-    ContentBodyFrame {
-        channel: channel,
-        body: payload.to_vec(),
-    }
+    content_body_bytes(channel, Bytes::copy_from_slice(payload))
+}
+
+/// Build a content body frame which shares the payload buffer instead of copying it.
+pub fn content_body_bytes(channel: u16, payload: Bytes) -> ContentBodyFrame {
+    ContentBodyFrame { channel, body: payload }
 }
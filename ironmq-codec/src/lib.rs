@@ -1,9 +1,15 @@
 //! Data structures and converter functions for dealing with AMQP frames.
 //!
 //! All the data types are in the `frame` module, the `codec` implements
-//! the encoding and the decoding.
+//! the encoding and the decoding. The `content` module helps to split and
+//! collect message contents which don't fit in one frame. The `dump` module
+//! formats frames and raw bytes for debugging. The `protocol` module is the
+//! state machine of a connection, used by both the client and the server.
 pub mod codec;
+pub mod content;
+pub mod dump;
 pub mod frame;
+pub mod protocol;
 
 #[macro_use]
 extern crate bitflags;
@@ -30,6 +36,35 @@ impl fmt::Display for FrameError {
 
 impl std::error::Error for FrameError {}
 
+/// The peer sent a protocol header which is not AMQP 0-9-1. The header is kept as it arrived,
+/// so the version the peer supports can be reported.
+#[derive(Debug)]
+pub struct ProtocolHeaderError {
+    pub header: [u8; 8],
+}
+
+impl ProtocolHeaderError {
+    /// The version in `major-minor-revision` form, or `None` if the header is not an AMQP header.
+    pub fn version(&self) -> Option<String> {
+        if &self.header[0..4] != b"AMQP" {
+            return None;
+        }
+
+        Some(format!("{}-{}-{}", self.header[5], self.header[6], self.header[7]))
+    }
+}
+
+impl fmt::Display for ProtocolHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version() {
+            Some(version) => write!(f, "Unsupported AMQP version {}", version),
+            None => write!(f, "Unsupported protocol header {:?}", self.header),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolHeaderError {}
+
 /// Shorthand for making errors with error code and error message.
 ///
 /// ```no_run
@@ -65,7 +100,7 @@ mod tests {
 
     #[test]
     fn encode_header_frame() {
-        let mut encoder = AMQPCodec {};
+        let mut encoder = AMQPCodec::default();
         let mut buf = BytesMut::with_capacity(1024);
 
         let res = encoder.encode(AMQPFrame::Header, &mut buf);
@@ -82,15 +117,15 @@ mod tests {
 
     #[test]
     fn encode_method_frame() {
-        let mut encoder = AMQPCodec {};
+        let mut encoder = AMQPCodec::default();
         let mut buf = BytesMut::with_capacity(1024);
 
         let args = frame::QueueBindArgs {
-            queue_name: "queue".into(),
-            exchange_name: "exchg".into(),
+            queue: "queue".into(),
+            exchange: "exchg".into(),
             routing_key: "key".into(),
             no_wait: false,
-            args: None
+            arguments: None
         };
 
         let res = encoder.encode(
@@ -120,4 +155,39 @@ mod tests {
 
         assert_eq!(expected, buf);
     }
+
+    #[test]
+    fn reply_codes_are_classified() {
+        use frame::ReplyCode;
+
+        assert_eq!(ReplyCode::from_code(404), Some(ReplyCode::NotFound));
+        assert_eq!(ReplyCode::from_code(999), None);
+        assert_eq!(u16::from(ReplyCode::ChannelError), 504);
+
+        assert!(ReplyCode::NotFound.is_soft_error());
+        assert!(!ReplyCode::NotFound.is_hard_error());
+        assert!(ReplyCode::FrameError.is_hard_error());
+        assert!(!ReplyCode::ReplySuccess.is_soft_error() && !ReplyCode::ReplySuccess.is_hard_error());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn frames_survive_json_round_trip() {
+        let mut args = frame::FieldTable::new();
+        args.insert("x-message-ttl".into(), frame::AMQPFieldValue::I32(1000));
+
+        let frames = vec![
+            AMQPFrame::Header,
+            frame::queue_declare(1, "queue"),
+            frame::basic_nack(1, 7, None),
+            frame::channel_close(1, 404, "Not found", 0x32, 0x0A),
+            AMQPFrame::ContentBody(frame::content_body(1, b"hello")),
+        ];
+
+        let json = serde_json::to_string(&(frames, args)).unwrap();
+        let (decoded, table): (Vec<AMQPFrame>, frame::FieldTable) = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(json, serde_json::to_string(&(decoded, table)).unwrap());
+        assert!(json.contains("\"BasicNack\""));
+    }
 }
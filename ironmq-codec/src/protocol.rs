@@ -0,0 +1,729 @@
+//! Transport agnostic state machine of an AMQP connection.
+//!
+//! `Protocol` checks the frames going in both directions: the order of the connection handshake,
+//! the lifecycle of the channels and the sequence of the content frames. It doesn't do any IO.
+//! Frames or raw bytes are passed in, and events and encoded bytes come out, so it works with any
+//! runtime and the protocol behaviour can be tested without sockets.
+//!
+//! Every violation is a connection error, the peer needs to be closed with the frame given by
+//! `ProtocolError::close_frame`.
+use crate::codec::{AMQPCodec, DEFAULT_FRAME_MAX};
+use crate::content::{Content, ContentAssembler};
+use crate::frame::{self, AMQPFrame, Channel, ClassMethod, MethodFrameArgs, ReplyCode};
+use crate::{FrameError, Result};
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::fmt;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Which side of the connection the state machine is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// Phases of the connection, named after the frame the connection waits for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Protocol header from the client.
+    Header,
+    /// `connection.start` from the server.
+    Start,
+    /// `connection.start-ok` from the client.
+    StartOk,
+    /// `connection.secure` or `connection.tune` from the server.
+    Tune,
+    /// `connection.secure-ok` from the client.
+    SecureOk,
+    /// `connection.tune-ok` from the client.
+    TuneOk,
+    /// `connection.open` from the client.
+    Open,
+    /// `connection.open-ok` from the server.
+    OpenOk,
+    /// The connection is open, channels can be used.
+    Opened,
+    /// `connection.close-ok` after one of the sides sent `connection.close`.
+    Closing,
+    /// The connection is closed, the socket can be closed.
+    Closed,
+}
+
+/// What the peer sent, after the checks.
+#[derive(Debug)]
+pub enum Event {
+    /// Protocol header of the client.
+    Header,
+    /// A method without content.
+    Method(Channel, ClassMethod, MethodFrameArgs),
+    /// A content carrying method (like `basic.publish`) with its complete content.
+    Content(Channel, ClassMethod, MethodFrameArgs, Box<Content>),
+    Heartbeat(Channel),
+}
+
+/// A frame which is not allowed in the current state of the connection or the channel.
+#[derive(Debug)]
+pub struct ProtocolError {
+    pub channel: Channel,
+    pub code: ReplyCode,
+    pub text: String,
+    /// The offending method, or zero if it was not a method frame.
+    pub class_method: ClassMethod,
+}
+
+impl ProtocolError {
+    /// The `connection.close` frame which needs to be sent to the peer.
+    pub fn close_frame(&self) -> AMQPFrame {
+        let (class_id, method_id) = frame::split_class_method(self.class_method);
+
+        frame::connection_close(0, self.code.into(), &self.text, class_id, method_id)
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} on channel {}: {}", self.code, self.channel, self.text)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ChannelState {
+    Open,
+    /// We sent `channel.close`, everything else than close and close-ok is dropped.
+    Closing,
+}
+
+/// The limits proposed in `connection.tune` and agreed in `connection.tune-ok`.
+#[derive(Clone, Copy, Debug)]
+struct Tuning {
+    channel_max: u16,
+    frame_max: u32,
+    heartbeat: u16,
+}
+
+/// Connection state machine, see the module documentation.
+#[derive(Debug)]
+pub struct Protocol {
+    role: Role,
+    phase: Phase,
+    channels: HashMap<Channel, ChannelState>,
+    /// Content carrying methods which wait for their content header and bodies.
+    pending_contents: HashMap<Channel, (ClassMethod, MethodFrameArgs)>,
+    contents: ContentAssembler,
+    proposed: Option<Tuning>,
+    tuning: Tuning,
+    /// Codec and buffers of the byte level interface.
+    codec: AMQPCodec,
+    input: BytesMut,
+    output: BytesMut,
+}
+
+impl Protocol {
+    pub fn new(role: Role) -> Protocol {
+        Protocol {
+            role,
+            phase: Phase::Header,
+            channels: HashMap::new(),
+            pending_contents: HashMap::new(),
+            contents: ContentAssembler::new(),
+            proposed: None,
+            tuning: Tuning {
+                channel_max: 0,
+                frame_max: DEFAULT_FRAME_MAX,
+                heartbeat: 0,
+            },
+            codec: AMQPCodec::new(DEFAULT_FRAME_MAX),
+            input: BytesMut::new(),
+            output: BytesMut::new(),
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Maximum channel number after tuning, zero means no limit.
+    pub fn channel_max(&self) -> u16 {
+        self.tuning.channel_max
+    }
+
+    /// Maximum frame size after tuning, zero means no limit.
+    pub fn frame_max(&self) -> u32 {
+        self.tuning.frame_max
+    }
+
+    /// Heartbeat interval in seconds after tuning, zero means no heartbeats.
+    pub fn heartbeat(&self) -> u16 {
+        self.tuning.heartbeat
+    }
+
+    /// Refuse the contents which are bigger than `size` with a connection error, zero means no
+    /// limit.
+    pub fn set_max_message_size(&mut self, size: u64) {
+        self.contents.set_max_body_size(size);
+    }
+
+    pub fn is_channel_open(&self, channel: Channel) -> bool {
+        self.channels.get(&channel) == Some(&ChannelState::Open)
+    }
+
+    /// Check a frame arrived from the peer. `None` means that the frame was dropped or it is
+    /// part of a content which is not complete yet.
+    pub fn receive_frame(&mut self, f: AMQPFrame) -> Result<Option<Event>> {
+        match f {
+            AMQPFrame::Header => {
+                self.handshake(true, 0)?;
+
+                Ok(Some(Event::Header))
+            }
+            AMQPFrame::Method(channel, cm, args) => self.receive_method(channel, cm, args),
+            AMQPFrame::ContentHeader(header) => {
+                let channel = header.channel;
+
+                if self.drops_incoming(channel) {
+                    return Ok(None);
+                }
+
+                if !self.pending_contents.contains_key(&channel) {
+                    return violation(channel, ReplyCode::UnexpectedFrame, "Content header without method", 0);
+                }
+
+                let content = self.contents.header(header).or_else(|e| content_error(channel, e))?;
+
+                Ok(content.map(|c| self.complete_content(c)))
+            }
+            AMQPFrame::ContentBody(body) => {
+                let channel = body.channel;
+
+                if self.drops_incoming(channel) {
+                    return Ok(None);
+                }
+
+                let content = self.contents.body(body).or_else(|e| content_error(channel, e))?;
+
+                Ok(content.map(|c| self.complete_content(c)))
+            }
+            AMQPFrame::Heartbeat(0) => Ok(Some(Event::Heartbeat(0))),
+            AMQPFrame::Heartbeat(channel) => {
+                violation(channel, ReplyCode::CommandInvalid, "Heartbeat is not on channel zero", 0)
+            }
+        }
+    }
+
+    /// Check a frame before it is sent to the peer.
+    pub fn send_frame(&mut self, f: &AMQPFrame) -> Result<()> {
+        match f {
+            AMQPFrame::Header => self.handshake(false, 0),
+            AMQPFrame::Method(channel, cm, args) => self.check_method(false, *channel, *cm, args),
+            AMQPFrame::ContentHeader(header) => self.check_channel(false, header.channel, 0),
+            AMQPFrame::ContentBody(body) => self.check_channel(false, body.channel, 0),
+            AMQPFrame::Heartbeat(_) => Ok(()),
+        }
+    }
+
+    /// Append bytes received from the peer.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+    }
+
+    /// Decode and check the next event from the fed bytes. `None` means that more bytes are
+    /// needed.
+    pub fn next_event(&mut self) -> Result<Option<Event>> {
+        while let Some(f) = self.codec.decode(&mut self.input)? {
+            if let Some(event) = self.receive_frame(f)? {
+                return Ok(Some(event));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Check and encode a frame, the bytes can be taken by `take_output`.
+    pub fn write_frame(&mut self, f: AMQPFrame) -> Result<()> {
+        self.send_frame(&f)?;
+        self.codec.encode(f, &mut self.output)
+    }
+
+    /// The encoded bytes which need to be sent to the peer.
+    pub fn take_output(&mut self) -> BytesMut {
+        self.output.split()
+    }
+
+    fn receive_method(&mut self, channel: Channel, cm: ClassMethod, args: MethodFrameArgs) -> Result<Option<Event>> {
+        if self.drops_incoming(channel) && !is_close(cm) {
+            return Ok(None);
+        }
+
+        self.check_method(true, channel, cm, &args)?;
+
+        if channel != 0 && (self.pending_contents.contains_key(&channel) || self.contents.is_in_progress(channel)) {
+            return violation(channel, ReplyCode::UnexpectedFrame, "Method frame in the middle of a content", cm);
+        }
+
+        if has_content(cm) {
+            self.pending_contents.insert(channel, (cm, args));
+
+            return Ok(None);
+        }
+
+        Ok(Some(Event::Method(channel, cm, args)))
+    }
+
+    fn check_method(&mut self, incoming: bool, channel: Channel, cm: ClassMethod, args: &MethodFrameArgs) -> Result<()> {
+        let (class_id, _) = frame::split_class_method(cm);
+
+        if class_id == frame::CONNECTION_CLASS {
+            if channel != 0 {
+                return violation(channel, ReplyCode::CommandInvalid, "Connection method on a non-zero channel", cm);
+            }
+
+            return self.connection_method(incoming, cm, args);
+        }
+
+        if self.phase != Phase::Opened {
+            return violation(channel, ReplyCode::CommandInvalid, "Connection is not open", cm);
+        }
+
+        match cm {
+            frame::CHANNEL_OPEN => {
+                if channel == 0 {
+                    return violation(channel, ReplyCode::ChannelError, "Channel zero cannot be opened", cm);
+                }
+
+                if self.tuning.channel_max != 0 && channel > self.tuning.channel_max {
+                    return violation(channel, ReplyCode::NotAllowed, "Channel number is above channel max", cm);
+                }
+
+                if self.channels.contains_key(&channel) {
+                    return violation(channel, ReplyCode::ChannelError, "Channel is already open", cm);
+                }
+
+                self.channels.insert(channel, ChannelState::Open);
+            }
+            frame::CHANNEL_CLOSE => {
+                self.check_channel(incoming, channel, cm)?;
+                self.cancel_content(channel);
+
+                if !incoming {
+                    self.channels.insert(channel, ChannelState::Closing);
+                }
+            }
+            frame::CHANNEL_CLOSE_OK => {
+                self.check_channel(incoming, channel, cm)?;
+                self.cancel_content(channel);
+                self.channels.remove(&channel);
+            }
+            _ => self.check_channel(incoming, channel, cm)?,
+        }
+
+        Ok(())
+    }
+
+    /// The channel needs to be open, or it is closing and a close frame goes through.
+    fn check_channel(&self, incoming: bool, channel: Channel, cm: ClassMethod) -> Result<()> {
+        match self.channels.get(&channel) {
+            Some(ChannelState::Open) => Ok(()),
+            Some(ChannelState::Closing) if incoming || is_close(cm) => Ok(()),
+            Some(ChannelState::Closing) => violation(channel, ReplyCode::ChannelError, "Channel is closing", cm),
+            None => violation(channel, ReplyCode::ChannelError, "Channel is not open", cm),
+        }
+    }
+
+    fn connection_method(&mut self, incoming: bool, cm: ClassMethod, args: &MethodFrameArgs) -> Result<()> {
+        match (cm, args) {
+            (frame::CONNECTION_TUNE, MethodFrameArgs::ConnectionTune(args)) => {
+                self.proposed = Some(Tuning {
+                    channel_max: args.channel_max,
+                    frame_max: args.frame_max,
+                    heartbeat: args.heartbeat,
+                });
+            }
+            (frame::CONNECTION_TUNE_OK, MethodFrameArgs::ConnectionTuneOk(args)) => {
+                if args.frame_max != 0 && args.frame_max < frame::FRAME_MIN_SIZE {
+                    return violation(
+                        0,
+                        ReplyCode::NotAllowed,
+                        format!("Frame max {} is below the minimum {}", args.frame_max, frame::FRAME_MIN_SIZE),
+                        cm,
+                    );
+                }
+
+                let server = self.proposed.unwrap_or(self.tuning);
+
+                self.tuning = Tuning {
+                    channel_max: negotiate(server.channel_max as u32, args.channel_max as u32) as u16,
+                    frame_max: negotiate(server.frame_max, args.frame_max),
+                    // zero heartbeat of the client means that it doesn't want heartbeats, otherwise
+                    // it cannot go above the server
+                    heartbeat: match args.heartbeat {
+                        0 => 0,
+                        hb => negotiate(server.heartbeat as u32, hb as u32) as u16,
+                    },
+                };
+            }
+            _ => (),
+        }
+
+        self.handshake(incoming, cm)?;
+
+        if cm == frame::CONNECTION_TUNE_OK {
+            self.codec.set_frame_max(self.tuning.frame_max);
+        }
+
+        Ok(())
+    }
+
+    /// Move the connection to the next phase, if the frame is expected in the current phase and
+    /// it comes from the right side. The protocol header is handled as a zero class method.
+    fn handshake(&mut self, incoming: bool, cm: ClassMethod) -> Result<()> {
+        let from_server = (self.role == Role::Server) != incoming;
+        let phase = self.phase;
+
+        let next = match (phase, cm) {
+            (Phase::Header, 0) if !from_server => Some(Phase::Start),
+            (Phase::Start, frame::CONNECTION_START) if from_server => Some(Phase::StartOk),
+            (Phase::StartOk, frame::CONNECTION_START_OK) if !from_server => Some(Phase::Tune),
+            (Phase::Tune, frame::CONNECTION_SECURE) if from_server => Some(Phase::SecureOk),
+            (Phase::SecureOk, frame::CONNECTION_SECURE_OK) if !from_server => Some(Phase::Tune),
+            (Phase::Tune, frame::CONNECTION_TUNE) if from_server => Some(Phase::TuneOk),
+            (Phase::TuneOk, frame::CONNECTION_TUNE_OK) if !from_server => Some(Phase::Open),
+            (Phase::Open, frame::CONNECTION_OPEN) if !from_server => Some(Phase::OpenOk),
+            (Phase::OpenOk, frame::CONNECTION_OPEN_OK) if from_server => Some(Phase::Opened),
+            (Phase::Opened, frame::CONNECTION_BLOCKED)
+            | (Phase::Opened, frame::CONNECTION_UNBLOCKED)
+            | (Phase::Opened, frame::CONNECTION_UPDATE_SECRET_OK)
+                if from_server =>
+            {
+                Some(Phase::Opened)
+            }
+            (Phase::Opened, frame::CONNECTION_UPDATE_SECRET) if !from_server => Some(Phase::Opened),
+            (Phase::Header, frame::CONNECTION_CLOSE) | (Phase::Closed, frame::CONNECTION_CLOSE) => None,
+            (_, frame::CONNECTION_CLOSE) => Some(Phase::Closing),
+            (Phase::Closing, frame::CONNECTION_CLOSE_OK) => Some(Phase::Closed),
+            _ => None,
+        };
+
+        match next {
+            Some(next) => {
+                self.phase = next;
+
+                if next == Phase::Closed {
+                    self.channels.clear();
+                    self.pending_contents.clear();
+                }
+
+                Ok(())
+            }
+            None if cm == 0 => violation(0, ReplyCode::UnexpectedFrame, "Unexpected protocol header", 0),
+            None => violation(0, ReplyCode::CommandInvalid, format!("Unexpected method in phase {:?}", phase), cm),
+        }
+    }
+
+    /// After we sent a close, the frames of the peer are dropped until the close-ok arrives.
+    fn drops_incoming(&self, channel: Channel) -> bool {
+        self.phase == Phase::Closing || self.channels.get(&channel) == Some(&ChannelState::Closing)
+    }
+
+    fn cancel_content(&mut self, channel: Channel) {
+        self.pending_contents.remove(&channel);
+        self.contents.cancel(channel);
+    }
+
+    fn complete_content(&mut self, content: Content) -> Event {
+        let channel = content.header.channel;
+        // content frames are accepted only if the method is pending
+        let (cm, args) = self.pending_contents.remove(&channel).unwrap();
+
+        Event::Content(channel, cm, args, Box::new(content))
+    }
+}
+
+/// Methods which are followed by a content header and content bodies.
+fn has_content(cm: ClassMethod) -> bool {
+    matches!(cm, frame::BASIC_PUBLISH | frame::BASIC_RETURN | frame::BASIC_DELIVER | frame::BASIC_GET_OK)
+}
+
+fn is_close(cm: ClassMethod) -> bool {
+    matches!(
+        cm,
+        frame::CHANNEL_CLOSE | frame::CHANNEL_CLOSE_OK | frame::CONNECTION_CLOSE | frame::CONNECTION_CLOSE_OK
+    )
+}
+
+/// The lower of the two limits, zero means that the side doesn't have a limit.
+fn negotiate(server: u32, client: u32) -> u32 {
+    match (server, client) {
+        (0, c) => c,
+        (s, 0) => s,
+        (s, c) => s.min(c),
+    }
+}
+
+fn violation<T, S: Into<String>>(channel: Channel, code: ReplyCode, text: S, cm: ClassMethod) -> Result<T> {
+    Err(Box::new(ProtocolError {
+        channel,
+        code,
+        text: text.into(),
+        class_method: cm,
+    }))
+}
+
+/// Errors of the content assembler have the reply code in them.
+fn content_error<T>(channel: Channel, err: crate::Error) -> Result<T> {
+    match err.downcast::<FrameError>() {
+        Ok(fe) => {
+            let code = ReplyCode::from_code(fe.code).unwrap_or(ReplyCode::FrameError);
+
+            violation(channel, code, fe.message, 0)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{content_body, content_header};
+
+    /// Pass the bytes between the two sides until there is nothing to say, and collect the events
+    /// of the server.
+    fn exchange(client: &mut Protocol, server: &mut Protocol) -> Vec<Event> {
+        let mut events = vec![];
+
+        loop {
+            let to_server = client.take_output();
+            let to_client = server.take_output();
+
+            if to_server.is_empty() && to_client.is_empty() {
+                return events;
+            }
+
+            server.feed(&to_server);
+            client.feed(&to_client);
+
+            while let Some(event) = server.next_event().unwrap() {
+                match event {
+                    Event::Header => server.write_frame(frame::connection_start(0)).unwrap(),
+                    Event::Method(_, frame::CONNECTION_START_OK, _) => {
+                        server.write_frame(frame::connection_tune(0, 2047, 131_072, 60)).unwrap()
+                    }
+                    Event::Method(_, frame::CONNECTION_OPEN, _) => {
+                        server.write_frame(frame::connection_open_ok(0)).unwrap()
+                    }
+                    Event::Method(ch, frame::CHANNEL_OPEN, _) => server.write_frame(frame::channel_open_ok(ch)).unwrap(),
+                    e => events.push(e),
+                }
+            }
+
+            while let Some(event) = client.next_event().unwrap() {
+                match event {
+                    Event::Method(_, frame::CONNECTION_START, _) => client
+                        .write_frame(frame::connection_start_ok("guest", "guest", frame::FieldTable::new()))
+                        .unwrap(),
+                    Event::Method(_, frame::CONNECTION_TUNE, _) => {
+                        client.write_frame(frame::connection_tune_ok(0, 2047, 131_072, 60)).unwrap();
+                        client.write_frame(frame::connection_open(0, "/")).unwrap();
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn open_pair() -> (Protocol, Protocol) {
+        let mut client = Protocol::new(Role::Client);
+        let mut server = Protocol::new(Role::Server);
+
+        client.write_frame(AMQPFrame::Header).unwrap();
+        exchange(&mut client, &mut server);
+
+        client.write_frame(frame::channel_open(1)).unwrap();
+        exchange(&mut client, &mut server);
+
+        (client, server)
+    }
+
+    fn error_code(result: Result<Option<Event>>) -> ReplyCode {
+        result.unwrap_err().downcast::<ProtocolError>().unwrap().code
+    }
+
+    #[test]
+    fn handshake_opens_the_connection() {
+        let (client, server) = open_pair();
+
+        assert_eq!(client.phase(), Phase::Opened);
+        assert_eq!(server.phase(), Phase::Opened);
+        assert_eq!(server.frame_max(), 131_072);
+        assert_eq!(server.channel_max(), 2047);
+        assert!(client.is_channel_open(1));
+        assert!(server.is_channel_open(1));
+    }
+
+    #[test]
+    fn method_before_open_is_command_invalid() {
+        let mut server = Protocol::new(Role::Server);
+        server.receive_frame(AMQPFrame::Header).unwrap();
+        server.send_frame(&frame::connection_start(0)).unwrap();
+
+        let result = server.receive_frame(frame::queue_declare(1, "queue"));
+
+        assert_eq!(error_code(result), ReplyCode::CommandInvalid);
+    }
+
+    #[test]
+    fn method_from_the_wrong_side_is_command_invalid() {
+        let mut server = Protocol::new(Role::Server);
+        server.receive_frame(AMQPFrame::Header).unwrap();
+
+        assert_eq!(error_code(server.receive_frame(frame::connection_start(0))), ReplyCode::CommandInvalid);
+    }
+
+    #[test]
+    fn method_on_unopened_channel_is_channel_error() {
+        let (_, mut server) = open_pair();
+
+        assert_eq!(error_code(server.receive_frame(frame::queue_declare(2, "queue"))), ReplyCode::ChannelError);
+        assert_eq!(error_code(server.receive_frame(frame::channel_open(1))), ReplyCode::ChannelError);
+        assert_eq!(error_code(server.receive_frame(frame::channel_open(3000))), ReplyCode::NotAllowed);
+    }
+
+    #[test]
+    fn content_is_given_with_its_method() {
+        let (mut client, mut server) = open_pair();
+
+        client.write_frame(frame::basic_publish(1, "exchange", "key")).unwrap();
+        client.write_frame(AMQPFrame::ContentHeader(content_header(1, 5))).unwrap();
+        client.write_frame(AMQPFrame::ContentBody(content_body(1, b"hello"))).unwrap();
+
+        match exchange(&mut client, &mut server).pop() {
+            Some(Event::Content(1, frame::BASIC_PUBLISH, MethodFrameArgs::BasicPublish(args), content)) => {
+                assert_eq!(args.exchange, "exchange");
+                assert_eq!(&content.body[..], b"hello");
+            }
+            e => panic!("Unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn content_above_max_message_size_is_resource_error() {
+        let (_, mut server) = open_pair();
+        server.set_max_message_size(4);
+
+        server.receive_frame(frame::basic_publish(1, "exchange", "key")).unwrap();
+
+        let result = server.receive_frame(AMQPFrame::ContentHeader(content_header(1, 1 << 40)));
+
+        assert_eq!(error_code(result), ReplyCode::ResourceError);
+    }
+
+    #[test]
+    fn method_in_the_middle_of_content_is_unexpected_frame() {
+        let (_, mut server) = open_pair();
+
+        server.receive_frame(frame::basic_publish(1, "exchange", "key")).unwrap();
+
+        let result = server.receive_frame(frame::queue_declare(1, "queue"));
+
+        assert_eq!(error_code(result), ReplyCode::UnexpectedFrame);
+    }
+
+    #[test]
+    fn content_header_without_method_is_unexpected_frame() {
+        let (_, mut server) = open_pair();
+
+        let result = server.receive_frame(AMQPFrame::ContentHeader(content_header(1, 5)));
+
+        assert_eq!(error_code(result), ReplyCode::UnexpectedFrame);
+    }
+
+    #[test]
+    fn frames_are_dropped_after_channel_close() {
+        let (_, mut server) = open_pair();
+
+        server.send_frame(&frame::channel_close(1, 404, "Not found", 0x3C, 0x28)).unwrap();
+
+        assert!(server.receive_frame(frame::basic_publish(1, "exchange", "key")).unwrap().is_none());
+        assert!(server.receive_frame(AMQPFrame::ContentHeader(content_header(1, 5))).unwrap().is_none());
+        assert!(server.send_frame(&frame::queue_declare_ok(1, "queue".into(), 0, 0)).is_err());
+
+        server.receive_frame(frame::channel_close_ok(1)).unwrap();
+
+        assert!(!server.is_channel_open(1));
+    }
+
+    fn tune_ok(frame_max: u32, heartbeat: u16) -> AMQPFrame {
+        let args = frame::ConnectionTuneOkArgs {
+            channel_max: 0,
+            frame_max,
+            heartbeat,
+        };
+
+        AMQPFrame::Method(0, frame::CONNECTION_TUNE_OK, MethodFrameArgs::ConnectionTuneOk(args))
+    }
+
+    /// Server which sent the tune and waits for the tune-ok.
+    fn tuning_server() -> Protocol {
+        let mut server = Protocol::new(Role::Server);
+
+        server.receive_frame(AMQPFrame::Header).unwrap();
+        server.send_frame(&frame::connection_start(0)).unwrap();
+        server
+            .receive_frame(frame::connection_start_ok("guest", "guest", frame::FieldTable::new()))
+            .unwrap();
+        server.send_frame(&frame::connection_tune(0, 2047, 131_072, 60)).unwrap();
+        server
+    }
+
+    #[test]
+    fn zero_heartbeat_of_client_turns_heartbeats_off() {
+        let mut server = tuning_server();
+
+        server.receive_frame(tune_ok(0, 0)).unwrap();
+
+        assert_eq!(server.heartbeat(), 0);
+
+    }
+
+    #[test]
+    fn heartbeat_is_the_lower_one() {
+        let mut server = tuning_server();
+
+        server.receive_frame(tune_ok(0, 600)).unwrap();
+
+        assert_eq!(server.heartbeat(), 60);
+
+        let mut server = tuning_server();
+
+        server.receive_frame(tune_ok(0, 10)).unwrap();
+
+        assert_eq!(server.heartbeat(), 10);
+    }
+
+    #[test]
+    fn frame_max_below_minimum_is_not_allowed() {
+        let mut server = tuning_server();
+
+        assert_eq!(error_code(server.receive_frame(tune_ok(1, 0))), ReplyCode::NotAllowed);
+
+        let mut server = tuning_server();
+
+        server.receive_frame(tune_ok(frame::FRAME_MIN_SIZE, 0)).unwrap();
+
+        assert_eq!(server.frame_max(), frame::FRAME_MIN_SIZE);
+    }
+
+    #[test]
+    fn heartbeat_is_allowed_only_on_channel_zero() {
+        let (_, mut server) = open_pair();
+
+        assert!(matches!(server.receive_frame(AMQPFrame::Heartbeat(0)), Ok(Some(Event::Heartbeat(0)))));
+        assert_eq!(error_code(server.receive_frame(AMQPFrame::Heartbeat(1))), ReplyCode::CommandInvalid);
+    }
+}
@@ -0,0 +1,326 @@
+//! Authentication of the clients in `connection.start-ok`.
+//!
+//! The users are read from a TOML file. The password hashes have the same format as the ones of
+//! RabbitMQ: base64 of a 4 byte salt followed by the SHA-256 of the salt and the password, so
+//! `rabbitmqctl hash_password` can generate them.
+//!
+//! The permissions of a user are given per virtual host, as in RabbitMQ. The regular expressions
+//! are matched against the names of the exchanges and queues, they aren't anchored, and an empty
+//! expression doesn't match anything. Configure is needed to declare, write to publish and to bind
+//! a queue, read to consume and to bind to an exchange. The default exchange is checked as
+//! `amq.default`.
+//!
+//! ```toml
+//! [[users]]
+//! name = "guest"
+//! password_hash = "..."
+//!
+//! [users.permissions."/"]
+//! configure = "^guest-.*"
+//! write = ".*"
+//! read = ".*"
+//! ```
+use crate::Result;
+use ironmq_codec::codec;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use subtle::ConstantTimeEq;
+
+const SALT_LEN: usize = 4;
+
+#[derive(Clone, Debug)]
+pub(crate) struct User {
+    pub(crate) name: String,
+    pub(crate) password_hash: String,
+    /// Permissions by virtual host, the user cannot open the other virtual hosts.
+    pub(crate) permissions: HashMap<String, Permissions>,
+}
+
+/// What the operation does with the exchange or the queue.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Access {
+    Configure,
+    Write,
+    Read,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Permissions {
+    configure: Regex,
+    write: Regex,
+    read: Regex,
+}
+
+#[derive(Debug, Deserialize)]
+struct UsersFile {
+    users: Vec<UserEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserEntry {
+    name: String,
+    password_hash: String,
+    #[serde(default)]
+    permissions: HashMap<String, PermissionsEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PermissionsEntry {
+    configure: String,
+    write: String,
+    read: String,
+}
+
+/// Username and password from the response of the client.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Credentials {
+    pub(crate) username: String,
+    pub(crate) password: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct UserStore {
+    users: HashMap<String, User>,
+}
+
+impl Default for UserStore {
+    /// Without a users file only `guest` with the password `guest` can log in, and it can do
+    /// anything in the `/` virtual host.
+    fn default() -> Self {
+        let mut permissions = HashMap::new();
+        permissions.insert("/".to_string(), Permissions::full());
+
+        UserStore::new(vec![User {
+            name: "guest".into(),
+            password_hash: hash_password("guest"),
+            permissions,
+        }])
+    }
+}
+
+impl UserStore {
+    pub(crate) fn new(users: Vec<User>) -> UserStore {
+        UserStore {
+            users: users.into_iter().map(|u| (u.name.clone(), u)).collect(),
+        }
+    }
+
+    /// Read the users file.
+    pub(crate) fn load(path: &str) -> Result<UserStore> {
+        let text = fs::read_to_string(path)?;
+
+        UserStore::parse(&text)
+    }
+
+    fn parse(text: &str) -> Result<UserStore> {
+        let file: UsersFile = toml::from_str(text)?;
+        let mut users = vec![];
+
+        for entry in file.users {
+            let mut permissions = HashMap::new();
+
+            for (vhost, p) in entry.permissions {
+                permissions.insert(vhost, Permissions::new(&p.configure, &p.write, &p.read)?);
+            }
+
+            users.push(User {
+                name: entry.name,
+                password_hash: entry.password_hash,
+                permissions,
+            });
+        }
+
+        Ok(UserStore::new(users))
+    }
+
+    /// Give back the user if it exists and the password is right.
+    pub(crate) fn authenticate(&self, credentials: &Credentials) -> Option<&User> {
+        self.users
+            .get(&credentials.username)
+            .filter(|u| check_password(&credentials.password, &u.password_hash))
+    }
+}
+
+impl Permissions {
+    pub(crate) fn new(configure: &str, write: &str, read: &str) -> Result<Permissions> {
+        Ok(Permissions {
+            configure: permission_regex(configure)?,
+            write: permission_regex(write)?,
+            read: permission_regex(read)?,
+        })
+    }
+
+    /// Everything is allowed.
+    pub(crate) fn full() -> Permissions {
+        Permissions::new(".*", ".*", ".*").unwrap()
+    }
+
+    /// Check if the operation is allowed on the exchange or queue.
+    pub(crate) fn allows(&self, access: Access, name: &str) -> bool {
+        let name = if name.is_empty() { "amq.default" } else { name };
+
+        match access {
+            Access::Configure => self.configure.is_match(name),
+            Access::Write => self.write.is_match(name),
+            Access::Read => self.read.is_match(name),
+        }
+    }
+}
+
+fn permission_regex(re: &str) -> Result<Regex> {
+    let re = if re.is_empty() { "^$" } else { re };
+
+    Ok(Regex::new(re)?)
+}
+
+/// Get the username and password from the response of the mechanism. PLAIN sends
+/// `authzid NUL username NUL password`, AMQPLAIN sends a field table with `LOGIN` and `PASSWORD`
+/// without the length of the table.
+///
+/// The response is binary, username and password which are not valid UTF-8 are refused.
+pub(crate) fn parse_response(mechanism: &str, response: &[u8]) -> Option<Credentials> {
+    match mechanism {
+        "PLAIN" => {
+            let mut parts = response.split(|b| *b == 0);
+            let _authzid = parts.next()?;
+            let username = parts.next()?;
+            let password = parts.next()?;
+
+            if parts.next().is_some() {
+                return None;
+            }
+
+            Some(Credentials {
+                username: String::from_utf8(username.to_vec()).ok()?,
+                password: String::from_utf8(password.to_vec()).ok()?,
+            })
+        }
+        "AMQPLAIN" => {
+            let table = codec::decode_table_fields(response).ok()?;
+
+            Some(Credentials {
+                username: table.get_str("LOGIN")?.into(),
+                password: table.get_str("PASSWORD")?.into(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Hash the password with a random salt from the random generator of the OS.
+pub(crate) fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+
+    OsRng.fill_bytes(&mut salt);
+
+    hash_with_salt(&salt, password)
+}
+
+fn hash_with_salt(salt: &[u8], password: &str) -> String {
+    let mut salted = salt.to_vec();
+    salted.extend_from_slice(&digest(salt, password));
+
+    base64::encode(salted)
+}
+
+fn digest(salt: &[u8], password: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(password.as_bytes());
+
+    hasher.finalize().to_vec()
+}
+
+/// The digests are compared in constant time, so the response time doesn't tell how much of the
+/// hash matched.
+fn check_password(password: &str, password_hash: &str) -> bool {
+    match base64::decode(password_hash) {
+        Ok(bytes) if bytes.len() > SALT_LEN => {
+            let (salt, hash) = bytes.split_at(SALT_LEN);
+
+            digest(salt, password).ct_eq(hash).into()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(username: &str, password: &str) -> Credentials {
+        Credentials {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    #[test]
+    fn rabbitmq_password_hash_is_accepted() {
+        // rabbitmqctl hash_password test12
+        let hash = "kI3GCqW5JLMJa4iX1lo7X4D6XbYqlLgxIs30+P6tENUV2POR";
+
+        assert!(check_password("test12", hash));
+        assert!(!check_password("test13", hash));
+        assert!(!check_password("test12", "not base64"));
+    }
+
+    #[test]
+    fn only_users_with_right_password_are_authenticated() {
+        let store = UserStore::default();
+
+        assert!(store.authenticate(&credentials("guest", "guest")).is_some());
+        assert!(store.authenticate(&credentials("guest", "wrong")).is_none());
+        assert!(store.authenticate(&credentials("nobody", "guest")).is_none());
+    }
+
+    #[test]
+    fn users_file_is_parsed() {
+        let text = r#"
+            [[users]]
+            name = "admin"
+            password_hash = "kI3GCqW5JLMJa4iX1lo7X4D6XbYqlLgxIs30+P6tENUV2POR"
+
+            [users.permissions.team]
+            configure = ""
+            write = ".*"
+            read = ".*"
+        "#;
+        let store = UserStore::parse(text).unwrap();
+        let admin = store.authenticate(&credentials("admin", "test12")).unwrap();
+
+        assert!(admin.permissions["team"].allows(Access::Write, "orders"));
+        assert!(!admin.permissions["team"].allows(Access::Configure, "orders"));
+        assert!(!admin.permissions.contains_key("/"));
+        assert!(store.authenticate(&credentials("guest", "guest")).is_none());
+    }
+
+    #[test]
+    fn permissions_are_checked_per_access() {
+        let p = Permissions::new("^team-", "", "team-|amq\\.default").unwrap();
+
+        assert!(p.allows(Access::Configure, "team-orders"));
+        assert!(!p.allows(Access::Configure, "orders"));
+        assert!(!p.allows(Access::Write, "team-orders"));
+        assert!(p.allows(Access::Read, "my-team-orders"));
+        assert!(p.allows(Access::Read, ""));
+        assert!(Permissions::new("(", ".*", ".*").is_err());
+    }
+
+    #[test]
+    fn plain_and_amqplain_responses_are_parsed() {
+        assert_eq!(parse_response("PLAIN", b"\0user\0secret"), Some(credentials("user", "secret")));
+        assert_eq!(parse_response("PLAIN", b"user\0secret"), None);
+        assert_eq!(parse_response("PLAIN", b"\0user\0\xFF\xFE"), None);
+
+        let response = b"\x05LOGINS\0\0\0\x04user\x08PASSWORDS\0\0\0\x06secret";
+
+        assert_eq!(parse_response("AMQPLAIN", response), Some(credentials("user", "secret")));
+        assert_eq!(parse_response("EXTERNAL", b""), None);
+    }
+}
@@ -2,10 +2,10 @@ pub(crate) mod conn;
 pub(crate) mod state;
 
 use crate::{ErrorScope, Result, RuntimeError};
-use ironmq_codec::frame;
+use ironmq_codec::frame::{self, ReplyCode};
 
 /// Helper to create channel error frames.
-pub(crate) fn error<T>(channel: frame::Channel, cm: u32, code: u16, text: &str) -> Result<T> {
+pub(crate) fn error<T>(channel: frame::Channel, cm: u32, code: ReplyCode, text: &str) -> Result<T> {
     let (class_id, method_id) = frame::split_class_method(cm);
 
     Err(Box::new(RuntimeError {
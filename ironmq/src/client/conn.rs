@@ -1,71 +1,232 @@
 use super::state::{self, Connection};
+use crate::config::Limits;
 use crate::{Context, Result};
+use futures::future;
 use futures::stream::StreamExt;
 use futures::SinkExt;
-use ironmq_codec::codec::AMQPCodec;
-use ironmq_codec::frame::{self, AMQPFrame, MethodFrameArgs};
-use log::{error, trace};
+use ironmq_codec::codec::{AMQPCodec, DEFAULT_FRAME_MAX};
+use ironmq_codec::content::content_body_frames;
+use ironmq_codec::frame::{self, AMQPFrame, MethodFrameArgs, ReplyCode};
+use ironmq_codec::protocol::{Event, ProtocolError};
+use ironmq_codec::{FrameError, ProtocolHeaderError};
+use log::{error, trace, warn};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::time;
 use tokio_util::codec::Framed;
 
-pub(crate) async fn handle_client(socket: TcpStream, context: Arc<Mutex<Context>>) -> Result<()> {
-    let (mut sink, mut stream) = Framed::new(socket, AMQPCodec {}).split();
-    let (consume_sink, mut consume_stream) = mpsc::channel::<AMQPFrame>(1);
-    let mut conn = state::new(context, consume_sink);
+/// Heartbeat timer of a connection, it is started when the client sent its tune-ok.
+struct Heartbeat {
+    interval: Duration,
+    timer: time::Interval,
+}
+
+pub(crate) async fn handle_client(socket: TcpStream, context: Arc<Mutex<Context>>, limits: Limits) -> Result<()> {
+    let (consume_sink, consume_stream) = mpsc::channel::<Vec<AMQPFrame>>(1);
+    let mut conn = state::new(context, limits, consume_sink);
+
+    let result = handle_frames(socket, &mut conn, consume_stream, limits.handshake_timeout).await;
+
+    // the consumers are removed however the connection ended, closed or the client disappeared
+    conn.cleanup().await?;
+
+    result
+}
+
+async fn handle_frames<S: AsyncRead + AsyncWrite + Unpin>(socket: S, conn: &mut Connection,
+                       mut consume_stream: mpsc::Receiver<Vec<AMQPFrame>>, handshake_timeout: u64) -> Result<()> {
+    let mut framed = Framed::new(socket, AMQPCodec::new(DEFAULT_FRAME_MAX));
+    // zero means that there is no timeout, after the open the timer is not polled anymore
+    let handshake = time::sleep(Duration::from_secs(handshake_timeout));
+    let mut handshake_done = handshake_timeout == 0;
+    tokio::pin!(handshake);
+    let mut heartbeat: Option<Heartbeat> = None;
+    let mut last_received = Instant::now();
+    let mut vhost_deleted: Option<watch::Receiver<bool>> = None;
 
     loop {
+        handshake_done = handshake_done || conn.is_open();
+
         tokio::select! {
-            data = stream.next() => {
+            _ = &mut handshake, if !handshake_done => {
+                warn!("Client didn't open the connection in {} seconds", handshake_timeout);
+
+                return Ok(());
+            }
+            data = framed.next() => {
                 trace!("Payload {:?}", data);
 
                 match data {
-                    Some(payload) =>
-                        match payload {
-                            Ok(frame) => match handle_client_frame(&mut conn, frame).await? {
-                                Some(response_frame) => {
-                                    if let AMQPFrame::Method(_, frame::CONNECTION_CLOSE_OK, _) = response_frame {
-                                        trace!("Outgoing {:?}", response_frame);
-                                        sink.send(response_frame).await?;
-
-                                        return Ok(());
-                                    } else {
-                                        trace!("Outgoing {:?}", response_frame);
-                                        sink.send(response_frame).await?;
-                                    }
+                    Some(Ok(frame)) => {
+                        last_received = Instant::now();
+
+                        let event = match conn.receive_frame(frame) {
+                            Ok(Some(event)) => event,
+                            Ok(None) => continue,
+                            Err(e) => match e.downcast::<ProtocolError>() {
+                                // the client gets the close and we wait for its close-ok
+                                Ok(pe) => {
+                                    error!("Protocol error {}", pe);
+
+                                    let close = pe.close_frame();
+
+                                    conn.send_frame(&close)?;
+                                    framed.send(close).await?;
+
+                                    continue;
                                 }
-                                None => (),
+                                Err(e) => return Err(e),
                             },
-                            Err(e) => return Err(Box::new(e)),
-                        },
+                        };
+
+                        if let Event::Method(_, frame::CONNECTION_TUNE_OK, _) = event {
+                            framed.codec_mut().set_frame_max(conn.frame_max());
+                            heartbeat = start_heartbeat(conn.heartbeat());
+                        }
+
+                        let open = matches!(event, Event::Method(_, frame::CONNECTION_OPEN, _));
+
+                        if let Some(response_frame) = handle_event(conn, event).await? {
+                            trace!("Outgoing {:?}", response_frame);
+
+                            conn.send_frame(&response_frame)?;
+                            framed.send(response_frame).await?;
+                        }
+
+                        if open {
+                            vhost_deleted = conn.vhost_deleted();
+                        }
+
+                        if conn.is_closed() {
+                            return Ok(());
+                        }
+                    }
+                    Some(Err(e)) => {
+                        // Unsupported protocol header is answered by our header, then the socket
+                        // is closed, so the client can see which version we support.
+                        if let Some(he) = e.downcast_ref::<ProtocolHeaderError>() {
+                            warn!("Client rejected: {}", he);
+                            framed.send(AMQPFrame::Header).await?;
+
+                            return Ok(());
+                        }
+
+                        // Malformed frames close the connection with the error code the
+                        // decoder gave, other errors just close the socket.
+                        if let Some(fe) = e.downcast_ref::<FrameError>() {
+                            error!("Frame error {:?}", fe);
+                            framed.send(frame::connection_close(0, fe.code, &fe.message, 0, 0)).await?;
+
+                            return Ok(());
+                        }
+
+                        return Err(e);
+                    }
                     None => break Ok(())
                 }
             }
+            _ = tick(&mut heartbeat) => {
+                if let Some(hb) = &heartbeat {
+                    if last_received.elapsed() > 2 * hb.interval {
+                        warn!("Client sent nothing in {:?}, closing the connection", last_received.elapsed());
+
+                        return Ok(());
+                    }
+                }
+
+                framed.send(AMQPFrame::Heartbeat(0)).await?;
+            }
+            _ = deleted(&mut vhost_deleted) => {
+                vhost_deleted = None;
+
+                let close = frame::connection_close(0, ReplyCode::ConnectionForced.into(), "Virtual host is deleted", 0, 0);
+
+                conn.send_frame(&close)?;
+                framed.send(close).await?;
+            }
             push = consume_stream.recv() => {
-                match push {
-                    Some(outgoing) =>
-                        sink.send(outgoing).await?,
-                    None =>
-                        ()  // TODO is it closed?
+                // TODO is it closed if push is None?
+                if let Some(frames) = push {
+                    let mut outgoing = Vec::with_capacity(frames.len());
+
+                    for f in frames {
+                        match f {
+                            // queues send the whole content in one body frame
+                            AMQPFrame::ContentBody(body) =>
+                                for b in content_body_frames(body.channel, &body.body, conn.frame_max()) {
+                                    outgoing.push(AMQPFrame::ContentBody(b));
+                                },
+                            f =>
+                                outgoing.push(f)
+                        }
+                    }
+
+                    // the channel can be closed since the consumer got the message
+                    if let Err(e) = outgoing.iter().try_for_each(|f| conn.send_frame(f)) {
+                        warn!("Delivery is dropped: {}", e);
+                        continue;
+                    }
+
+                    // deliver, header and body frames go out with one flush
+                    framed.send(outgoing).await?;
                 }
             }
         }
     }
 }
 
-//type SinkType = SplitSink<Framed<TcpStream, AMQPCodec>, AMQPFrame>;
+/// Heartbeats are sent at the half of the negotiated interval, zero means no heartbeats.
+fn start_heartbeat(seconds: u16) -> Option<Heartbeat> {
+    if seconds == 0 {
+        return None;
+    }
 
-async fn handle_client_frame(conn: &mut Connection, f: AMQPFrame) -> Result<Option<AMQPFrame>> {
-    use AMQPFrame::*;
+    let interval = Duration::from_secs(seconds as u64);
+    let period = interval / 2;
+
+    Some(Heartbeat {
+        interval,
+        timer: time::interval_at(time::Instant::now() + period, period),
+    })
+}
+
+/// Wait for the next heartbeat, if heartbeats are off it never returns.
+async fn tick(heartbeat: &mut Option<Heartbeat>) {
+    match heartbeat {
+        Some(hb) => {
+            hb.timer.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
 
-    match f {
-        Header => Ok(Some(frame::connection_start(0))),
-        Method(ch, _, mf) => handle_method_frame(conn, ch, mf).await,
-        ContentHeader(ch) => conn.receive_content_header(ch).await,
-        ContentBody(cb) => conn.receive_content_body(cb).await,
+/// Wait until the virtual host of the connection is deleted.
+async fn deleted(receiver: &mut Option<watch::Receiver<bool>>) {
+    match receiver {
+        Some(r) => {
+            // error means that the virtual host is gone as well
+            let _ = r.changed().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+//type SinkType = SplitSink<Framed<TcpStream, AMQPCodec>, AMQPFrame>;
+
+async fn handle_event(conn: &mut Connection, event: Event) -> Result<Option<AMQPFrame>> {
+    match event {
+        Event::Header => Ok(Some(frame::connection_start(0))),
+        // receiving the heartbeat already counted as traffic
+        Event::Heartbeat(_) => Ok(None),
+        Event::Method(ch, _, ma) => handle_method_frame(conn, ch, ma).await,
+        Event::Content(ch, _, MethodFrameArgs::BasicPublish(args), content) => {
+            conn.basic_publish(ch, args, *content).await
+        }
         _ => {
-            error!("Unhandler frame type {:?}", f);
+            error!("Unhandler event {:?}", event);
             Ok(None)
         }
     }
@@ -76,16 +237,19 @@ async fn handle_method_frame(conn: &mut Connection, channel: frame::Channel,
     use MethodFrameArgs::*;
 
     match ma {
-        ConnectionStartOk(_) => Ok(Some(frame::connection_tune(0))),
+        ConnectionStartOk(args) => conn.connection_start_ok(args).await,
+        ConnectionSecureOk(_) => conn.connection_tune().await,
+        ConnectionUpdateSecret(args) => conn.connection_update_secret(args).await,
         ConnectionTuneOk(_) => Ok(None),
         ConnectionOpen(args) => conn.connection_open(channel, args).await,
         ConnectionClose(args) => conn.connection_close(args).await,
+        ConnectionCloseOk => Ok(None),
         ChannelOpen => conn.channel_open(channel).await,
         ChannelClose(args) => conn.channel_close(channel, args).await,
+        ChannelCloseOk => Ok(None),
         ExchangeDeclare(args) => conn.exchange_declare(channel, args).await,
         QueueDeclare(args) => conn.queue_declare(channel, args).await,
         QueueBind(args) => conn.queue_bind(channel, args).await,
-        BasicPublish(args) => conn.basic_publish(channel, args).await,
         BasicConsume(args) => conn.basic_consume(channel, args).await,
         _ => {
             error!("Unhandler method frame type {:?}", ma);
@@ -93,3 +257,79 @@ async fn handle_method_frame(conn: &mut Connection, channel: frame::Channel,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth, vhost};
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_client_is_closed_after_handshake_timeout() {
+        let context = Context {
+            users: auth::UserStore::default(),
+            vhosts: vhost::start(vec!["/"]),
+        };
+        let (consume_sink, consume_stream) = mpsc::channel(1);
+        let mut conn = state::new(Arc::new(Mutex::new(context)), Limits::default(), consume_sink);
+        let (mut client, server) = tokio::io::duplex(1024);
+        let start = time::Instant::now();
+
+        let (result, closed) = tokio::join!(handle_frames(server, &mut conn, consume_stream, 10), async {
+            let mut buf = [0u8; 8];
+            let n = client.read(&mut buf).await.unwrap();
+
+            (n, start.elapsed())
+        });
+
+        assert!(result.is_ok());
+        // the client doesn't get anything, the socket is just closed
+        assert_eq!(closed.0, 0);
+        assert!(closed.1 >= Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn vhost_deleted_by_reload_closes_its_connections() {
+        let path = std::env::temp_dir().join(format!("ironmq-reload-{}.toml", std::process::id()));
+        let path = path.to_str().unwrap();
+        let context = Arc::new(Mutex::new(Context {
+            users: auth::UserStore::default(),
+            vhosts: vhost::start(vec!["/"]),
+        }));
+        let (consume_sink, consume_stream) = mpsc::channel(1);
+        let mut conn = state::new(context.clone(), Limits::default(), consume_sink);
+        let (client, server) = tokio::io::duplex(4096);
+        let mut client = Framed::new(client, AMQPCodec::new(DEFAULT_FRAME_MAX));
+
+        let (result, close) = tokio::join!(handle_frames(server, &mut conn, consume_stream, 0), async {
+            client.send(AMQPFrame::Header).await.unwrap();
+            client.next().await.unwrap().unwrap();
+            client
+                .send(frame::connection_start_ok("guest", "guest", frame::FieldTable::new()))
+                .await
+                .unwrap();
+            client.next().await.unwrap().unwrap();
+            client.send(frame::connection_tune_ok(0, 2047, 131_072, 0)).await.unwrap();
+            client.send(frame::connection_open(0, "/")).await.unwrap();
+            client.next().await.unwrap().unwrap();
+
+            std::fs::write(path, "[[vhosts]]\nname = \"team\"\n").unwrap();
+            crate::reload(path, &context).await.unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            let close = client.next().await.unwrap().unwrap();
+            client.send(frame::connection_close_ok(0)).await.unwrap();
+
+            close
+        });
+
+        assert!(result.is_ok());
+
+        match close {
+            AMQPFrame::Method(0, frame::CONNECTION_CLOSE, MethodFrameArgs::ConnectionClose(args)) => {
+                assert_eq!(args.reply_code, u16::from(ReplyCode::ConnectionForced));
+            }
+            f => panic!("Connection close expected, got {:?}", f),
+        }
+    }
+}
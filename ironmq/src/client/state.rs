@@ -1,93 +1,243 @@
+use crate::auth::{self, Access, Permissions};
+use crate::config::Limits;
 use crate::{Context, Result, RuntimeError};
 use crate::exchange::{handler::ExchangeCommandSink, handler::ExchangeCommand, manager::ExchangeManager};
 use crate::message;
 use crate::queue::{manager::QueueManager};
-use ironmq_codec::frame::{self, AMQPFrame, Channel};
-use log::info;
+use crate::vhost::VirtualHost;
+use ironmq_codec::content::Content;
+use ironmq_codec::frame::{self, AMQPFrame, Channel, ReplyCode};
+use ironmq_codec::protocol::{Event, Phase, Protocol, Role};
+use log::{info, warn};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, watch, Mutex};
 use uuid::Uuid;
 
 pub(crate) type MaybeFrame = Result<Option<AMQPFrame>>;
 
-pub(crate) const NOT_FOUND: u16 = 404;
-pub(crate) const PRECONDITION_FAILED: u16 = 406;
-pub(crate) const CHANNEL_ERROR: u16 = 504;
-pub(crate) const NOT_ALLOWED: u16 = 530;
-
 /// All the transient data of a connection are stored here.
 pub(crate) struct Connection {
     /// Unique ID of the connection.
     id: String,
     /// Context servers as a dependency holder, it keeps the references of the services.
     context: Arc<Mutex<Context>>,
-    /// Opened channels by this connection.
-    open_channels: HashMap<Channel, ()>,
+    /// The virtual host opened by the connection.
+    vhost: Option<VirtualHost>,
     /// Declared exchanges by this connection.
     exchanges: HashMap<String, ExchangeCommandSink>,
     /// Declared queues by this connection.
     queues: HashMap<String, message::MessageChannel>,
     /// Consumed queues by this connection, consumer_tag -> queue_name
     consumed_queues: HashMap<String, String>,
-    in_flight_contents: HashMap<Channel, PublishedContent>,
-    outgoing: mpsc::Sender<AMQPFrame>
+    /// The user who logged in.
+    user: Option<auth::User>,
+    /// Permissions of the user in the opened virtual host.
+    permissions: Option<Permissions>,
+    /// Authentication failed and the client cannot handle a connection close, so the socket is
+    /// simply closed.
+    refused: bool,
+    /// The limits we propose in tune, the negotiated ones are in `protocol`.
+    limits: Limits,
+    /// Checks the order of the frames, tracks the channels and collects the contents.
+    protocol: Protocol,
+    outgoing: mpsc::Sender<Vec<AMQPFrame>>
 }
 
-#[derive(Debug)]
-struct PublishedContent {
-    channel: Channel,
-    exchange: String,
-    length: Option<u64>,
-    content: Option<Vec<u8>>
-}
+pub(crate) fn new(context: Arc<Mutex<Context>>, limits: Limits, outgoing: mpsc::Sender<Vec<AMQPFrame>>) -> Connection {
+    let mut protocol = Protocol::new(Role::Server);
+    protocol.set_max_message_size(limits.max_message_size);
 
-pub(crate) fn new(context: Arc<Mutex<Context>>, outgoing: mpsc::Sender<AMQPFrame>) -> Connection {
     Connection {
         id: Uuid::new_v4().to_hyphenated().to_string(),
         context: context,
-        open_channels: HashMap::new(),
+        vhost: None,
         exchanges: HashMap::new(),
         queues: HashMap::new(),
         consumed_queues: HashMap::new(),
-        in_flight_contents: HashMap::new(),
+        user: None,
+        permissions: None,
+        refused: false,
+        limits,
+        protocol,
         outgoing: outgoing
     }
 }
 
 impl Connection {
-    pub(crate) async fn connection_open(&self, channel: Channel, args: frame::ConnectionOpenArgs) -> MaybeFrame {
-        if args.virtual_host != "/" {
-            connection_error(NOT_ALLOWED, "Cannot connect to virtualhost", frame::CONNECTION_OPEN)
-        } else {
-            Ok(Some(frame::connection_open_ok(channel)))
+    pub(crate) fn frame_max(&self) -> u32 {
+        self.protocol.frame_max()
+    }
+
+    /// Negotiated heartbeat interval in seconds.
+    pub(crate) fn heartbeat(&self) -> u16 {
+        self.protocol.heartbeat()
+    }
+
+    /// The client finished the handshake and opened a virtual host.
+    pub(crate) fn is_open(&self) -> bool {
+        self.protocol.phase() == Phase::Opened
+    }
+
+    /// The connection is closed after the close and close-ok methods, or if the login was refused.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.refused || self.protocol.phase() == Phase::Closed
+    }
+
+    /// Check the frame of the client, see `Protocol::receive_frame`.
+    pub(crate) fn receive_frame(&mut self, f: AMQPFrame) -> Result<Option<Event>> {
+        self.protocol.receive_frame(f)
+    }
+
+    /// Check the frame before it is sent to the client, see `Protocol::send_frame`.
+    pub(crate) fn send_frame(&mut self, f: &AMQPFrame) -> Result<()> {
+        self.protocol.send_frame(f)
+    }
+
+    /// Check the credentials of the client. If the login is refused, clients with the
+    /// `authentication_failure_close` capability get a connection close, others are disconnected.
+    pub(crate) async fn connection_start_ok(&mut self, args: frame::ConnectionStartOkArgs) -> MaybeFrame {
+        let ctx = self.context.lock().await;
+        let user = auth::parse_response(&args.mechanism, &args.response)
+            .and_then(|credentials| ctx.users.authenticate(&credentials));
+
+        match user {
+            Some(user) => {
+                info!("User {} logged in", user.name);
+
+                self.user = Some(user.clone());
+                drop(ctx);
+
+                self.connection_tune().await
+            }
+            None => {
+                warn!("Login was refused using authentication mechanism {}", args.mechanism);
+
+                let failure_close = args
+                    .client_properties
+                    .as_ref()
+                    .and_then(|props| props.get_table("capabilities"))
+                    .and_then(|caps| caps.get_bool("authentication_failure_close"))
+                    .unwrap_or(false);
+
+                if failure_close {
+                    let text = format!("Login was refused using authentication mechanism {}", args.mechanism);
+
+                    connection_error(ReplyCode::AccessRefused, &text, frame::CONNECTION_START_OK)
+                } else {
+                    self.refused = true;
+
+                    Ok(None)
+                }
+            }
         }
     }
 
-    pub(crate) async fn connection_close(&self, _args: frame::ConnectionCloseArgs) -> MaybeFrame {
-        // TODO cleanup
-        let mut ctx = self.context.lock().await;
-        for (consumer_tag, queue_name) in &self.consumed_queues {
-            ctx.queues.cancel(queue_name.to_string(), consumer_tag.to_string()).await?;
+    /// Propose the configured limits. The protocol keeps the lower of these and the values of the
+    /// client's tune-ok, and checks the channel numbers and frame sizes against them. A zero
+    /// heartbeat of the client turns the heartbeats off.
+    pub(crate) async fn connection_tune(&self) -> MaybeFrame {
+        let limits = &self.limits;
+
+        Ok(Some(frame::connection_tune(0, limits.channel_max, limits.frame_max, limits.heartbeat)))
+    }
+
+    /// Notified when the opened virtual host is deleted.
+    pub(crate) fn vhost_deleted(&self) -> Option<watch::Receiver<bool>> {
+        self.vhost.as_ref().map(|vhost| vhost.deleted())
+    }
+
+    /// Bind the connection to the virtual host if it exists and the user has permissions there.
+    pub(crate) async fn connection_open(&mut self, channel: Channel, args: frame::ConnectionOpenArgs) -> MaybeFrame {
+        let ctx = self.context.lock().await;
+        let permissions = self
+            .user
+            .as_ref()
+            .and_then(|user| user.permissions.get(&args.virtual_host))
+            .cloned();
+
+        match (ctx.vhosts.get(&args.virtual_host), permissions) {
+            (Some(vhost), Some(permissions)) => {
+                info!("Connection {} opened virtual host {}", self.id, vhost.name);
+
+                self.vhost = Some(vhost);
+                self.permissions = Some(permissions);
+
+                Ok(Some(frame::connection_open_ok(channel)))
+            }
+            (Some(_), None) => {
+                connection_error(ReplyCode::NotAllowed, "Access to virtual host refused", frame::CONNECTION_OPEN)
+            }
+            (None, _) => connection_error(ReplyCode::NotAllowed, "Cannot connect to virtualhost", frame::CONNECTION_OPEN),
+        }
+    }
+
+    /// Give back the error text if the user doesn't have the permission to the exchange or queue.
+    fn refused(&self, access: Access, kind: &str, name: &str) -> Option<String> {
+        match &self.permissions {
+            Some(p) if p.allows(access, name) => None,
+            _ => Some(format!(
+                "Access to {} '{}' in virtual host '{}' refused for user '{}'",
+                kind,
+                name,
+                self.vhost.as_ref().map_or("", |v| v.name.as_str()),
+                self.user.as_ref().map_or("", |u| u.name.as_str())
+            )),
+        }
+    }
+
+    /// The new secret is the password of the logged in user, it is checked like the one in
+    /// `connection.start-ok`.
+    pub(crate) async fn connection_update_secret(&self, args: frame::ConnectionUpdateSecretArgs) -> MaybeFrame {
+        let ctx = self.context.lock().await;
+        let accepted = match (&self.user, String::from_utf8(args.new_secret.to_vec())) {
+            (Some(user), Ok(password)) => {
+                let credentials = auth::Credentials {
+                    username: user.name.clone(),
+                    password,
+                };
+
+                ctx.users.authenticate(&credentials).is_some()
+            }
+            _ => false,
+        };
+
+        if !accepted {
+            warn!("Secret update was refused, reason: {}", args.reason);
+
+            let text = "Secret update was refused";
+
+            return connection_error(ReplyCode::AccessRefused, text, frame::CONNECTION_UPDATE_SECRET);
         }
 
+        info!("Client updated its secret, reason: {}", args.reason);
+
+        Ok(Some(frame::connection_update_secret_ok(0)))
+    }
+
+    /// The consumers are cancelled in `cleanup` when the connection loop ends.
+    pub(crate) async fn connection_close(&self, _args: frame::ConnectionCloseArgs) -> MaybeFrame {
         Ok(Some(frame::connection_close_ok(0)))
     }
 
-    pub(crate) async fn channel_open(&mut self, channel: Channel) -> MaybeFrame {
-        if self.open_channels.contains_key(&channel) {
-            channel_error(channel, CHANNEL_ERROR, "Channel already opened", frame::CHANNEL_OPEN)
-        } else {
-            self.open_channels.insert(channel, ());
-            Ok(Some(frame::channel_open_ok(channel)))
+    /// Cancel the consumers of the connection, so the queues don't deliver to a closed socket.
+    pub(crate) async fn cleanup(&mut self) -> Result<()> {
+        if let Some(vhost) = self.vhost.as_mut() {
+            for (consumer_tag, queue_name) in self.consumed_queues.drain() {
+                vhost.queues.cancel(queue_name, consumer_tag).await?;
+            }
         }
+
+        Ok(())
     }
 
-    pub(crate) async fn channel_close(&mut self, channel: Channel, _args: frame::ChannelCloseArgs) -> MaybeFrame {
-        let mut ctx = self.context.lock().await;
-        ctx.exchanges.clone_connection("", "").await?;
+    pub(crate) async fn channel_open(&mut self, channel: Channel) -> MaybeFrame {
+        Ok(Some(frame::channel_open_ok(channel)))
+    }
 
-        self.open_channels.remove(&channel);
+    pub(crate) async fn channel_close(&mut self, channel: Channel, _args: frame::ChannelCloseArgs) -> MaybeFrame {
+        let vhost = opened(&mut self.vhost);
+        vhost.exchanges.clone_connection("", "").await?;
 
         Ok(Some(frame::channel_close_ok(channel)))
     }
@@ -95,10 +245,16 @@ impl Connection {
     pub(crate) async fn exchange_declare(&mut self, channel: Channel, args: frame::ExchangeDeclareArgs) -> MaybeFrame {
         let no_wait = args.flags.contains(frame::ExchangeDeclareFlags::NO_WAIT);
         let passive = args.flags.contains(frame::ExchangeDeclareFlags::PASSIVE);
-        let exchange_name = args.exchange_name.clone();
+        let exchange_name = args.exchange.clone();
 
-        let mut ctx = self.context.lock().await;
-        let result = ctx.exchanges.declare(args.into(), passive, &self.id).await;
+        if !passive {
+            if let Some(text) = self.refused(Access::Configure, "exchange", &exchange_name) {
+                return channel_error(channel, ReplyCode::AccessRefused, &text, frame::EXCHANGE_DECLARE);
+            }
+        }
+
+        let vhost = opened(&mut self.vhost);
+        let result = vhost.exchanges.declare(args.into(), passive, &self.id).await;
 
         match result {
             Ok(ch) => {
@@ -122,99 +278,95 @@ impl Connection {
     }
 
     pub(crate) async fn queue_declare(&mut self, channel: Channel, args: frame::QueueDeclareArgs) -> MaybeFrame {
-        let mut ctx = self.context.lock().await;
-        ctx.queues.declare(args.name.clone()).await?;
+        if let Some(text) = self.refused(Access::Configure, "queue", &args.queue) {
+            return channel_error(channel, ReplyCode::AccessRefused, &text, frame::QUEUE_DECLARE);
+        }
 
-        Ok(Some(frame::queue_declare_ok(channel, args.name, 0, 0)))
+        let vhost = opened(&mut self.vhost);
+        vhost.queues.declare(args.queue.clone()).await?;
+
+        Ok(Some(frame::queue_declare_ok(channel, args.queue, 0, 0)))
     }
 
+    /// Binding writes to the queue and reads from the exchange.
     pub(crate) async fn queue_bind(&mut self, channel: Channel, args: frame::QueueBindArgs,) -> MaybeFrame {
-        let mut ctx = self.context.lock().await;
+        let refused = self
+            .refused(Access::Write, "queue", &args.queue)
+            .or_else(|| self.refused(Access::Read, "exchange", &args.exchange));
+
+        if let Some(text) = refused {
+            return channel_error(channel, ReplyCode::AccessRefused, &text, frame::QUEUE_BIND);
+        }
+
+        let vhost = opened(&mut self.vhost);
 
-        if let Ok(ch) = ctx.queues.get_channel(args.queue_name).await {
-            ctx.exchanges.bind_queue(args.exchange_name, ch).await?;
+        if let Ok(ch) = vhost.queues.get_channel(args.queue).await {
+            vhost.exchanges.bind_queue(args.exchange, ch).await?;
         } else {
         }
 
         Ok(Some(frame::queue_bind_ok(channel)))
     }
 
-    pub(crate) async fn basic_publish(&mut self, channel: Channel, args: frame::BasicPublishArgs) -> MaybeFrame {
-        if !self.exchanges.contains_key(&args.exchange_name) {
-            channel_error(channel, NOT_FOUND, "Exchange not found", frame::BASIC_PUBLISH)
-        } else {
-            // TODO check if there is in flight content in the channel -> error
-            self.in_flight_contents.insert(channel, PublishedContent {
-                channel: channel,
-                exchange: args.exchange_name,
-                length: None,
-                content: None
-            });
-
-            Ok(None)
+    /// Send the completely received content to the exchange it was published to.
+    pub(crate) async fn basic_publish(&mut self, channel: Channel, args: frame::BasicPublishArgs, content: Content) -> MaybeFrame {
+        if let Some(text) = self.refused(Access::Write, "exchange", &args.exchange) {
+            return channel_error(channel, ReplyCode::AccessRefused, &text, frame::BASIC_PUBLISH);
         }
-    }
 
-    pub(crate) async fn basic_consume(&mut self, channel: Channel, args: frame::BasicConsumeArgs) -> MaybeFrame {
-        let mut ctx = self.context.lock().await;
-        ctx.queues.consume(args.queue.clone(), args.consumer_tag.clone(), self.outgoing.clone()).await?;
-        self.consumed_queues.insert(args.consumer_tag.clone(), args.queue);
+        match self.exchanges.get(&args.exchange) {
+            Some(ch) => {
+                info!("Receive content with length {}", content.body.len());
 
-        Ok(Some(frame::basic_consume_ok(channel, args.consumer_tag)))
-    }
+                let msg = message::Message {
+                    source_connection: self.id.clone(),
+                    properties: content.header.properties,
+                    content: content.body,
+                };
 
-    pub(crate) async fn receive_content_header(&mut self, header: frame::ContentHeaderFrame) -> MaybeFrame {
-        // TODO collect info into a data struct
-        info!("Receive content with length {}", header.body_size);
+                ch.send(ExchangeCommand::Message(msg)).await?;
 
-        if let Some(pc) = self.in_flight_contents.get_mut(&header.channel) {
-            pc.length = Some(header.body_size);
+                Ok(None)
+            }
+            None => channel_error(channel, ReplyCode::NotFound, "Exchange not found", frame::BASIC_PUBLISH),
         }
-
-        Ok(None)
     }
 
-    pub(crate) async fn receive_content_body(&mut self, body: frame::ContentBodyFrame) -> MaybeFrame {
-        info!("Receive content with length {}", body.body.len());
+    pub(crate) async fn basic_consume(&mut self, channel: Channel, args: frame::BasicConsumeArgs) -> MaybeFrame {
+        if let Some(text) = self.refused(Access::Read, "queue", &args.queue) {
+            return channel_error(channel, ReplyCode::AccessRefused, &text, frame::BASIC_CONSUME);
+        }
 
-        if let Some(pc) = self.in_flight_contents.remove(&body.channel) {
-            let msg = message::Message {
-                source_connection: self.id.clone(),
-                content: body.body
-            };
+        let vhost = opened(&mut self.vhost);
+        vhost.queues.consume(args.queue.clone(), args.consumer_tag.clone(), self.outgoing.clone()).await?;
+        self.consumed_queues.insert(args.consumer_tag.clone(), args.queue);
 
-            match self.exchanges.get(&pc.exchange) {
-                Some(ch) => {
-                    ch.send(ExchangeCommand::Message(msg)).await?;
-                    Ok(None)
-                },
-                None =>
-                    // TODO error, exchange cannot be found
-                    Ok(None)
-            }
-        } else {
-            Ok(None)
-        }
+        Ok(Some(frame::basic_consume_ok(channel, args.consumer_tag)))
     }
 }
 
-fn channel_error(channel: Channel, code: u16, text: &str, cm_id: u32) -> MaybeFrame {
+/// The channel methods come after `connection.open`, the protocol checks that.
+fn opened(vhost: &mut Option<VirtualHost>) -> &mut VirtualHost {
+    vhost.as_mut().expect("Virtual host is not opened")
+}
+
+fn channel_error(channel: Channel, code: ReplyCode, text: &str, cm_id: u32) -> MaybeFrame {
     let (cid, mid) = frame::split_class_method(cm_id);
 
     Ok(Some(frame::channel_close(
                 channel,
-                code,
+                code.into(),
                 text,
                 cid,
                 mid)))
 }
 
-fn connection_error(code: u16, text: &str, cm_id: u32) -> MaybeFrame {
+fn connection_error(code: ReplyCode, text: &str, cm_id: u32) -> MaybeFrame {
     let (cid, mid) = frame::split_class_method(cm_id);
 
     Ok(Some(frame::connection_close(
                 0,
-                code,
+                code.into(),
                 text,
                 cid,
                 mid)))
@@ -0,0 +1,147 @@
+//! Configuration of the server, read from a TOML file.
+//!
+//! ```toml
+//! [network]
+//! listen = "127.0.0.1:5672"
+//!
+//! [limits]
+//! channel_max = 2047
+//! frame_max = 131072
+//! heartbeat = 60
+//! handshake_timeout = 10
+//! max_message_size = 134217728
+//!
+//! [auth]
+//! users_file = "users.toml"
+//!
+//! [[vhosts]]
+//! name = "/"
+//! ```
+//!
+//! Every section and field is optional, the missing ones get their default values.
+use crate::Result;
+use serde::Deserialize;
+use std::fs;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct Config {
+    pub(crate) network: Network,
+    pub(crate) limits: Limits,
+    pub(crate) auth: Auth,
+    /// The virtual hosts, they can be changed by sending `SIGHUP` to the server.
+    pub(crate) vhosts: Vec<VirtualHost>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct Network {
+    /// Address the server listens on.
+    pub(crate) listen: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Auth {
+    /// The file of the users, see `auth`. Without that only guest can log in.
+    pub(crate) users_file: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub(crate) struct VirtualHost {
+    pub(crate) name: String,
+}
+
+/// Limits of the connections. The first three are proposed in `connection.tune`, the client can
+/// lower them in `tune-ok`. Zero means no limit.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub(crate) struct Limits {
+    pub(crate) channel_max: u16,
+    pub(crate) frame_max: u32,
+    /// Heartbeat interval in seconds. The client can lower it in the `tune-ok`, zero there turns the
+    /// heartbeats off.
+    pub(crate) heartbeat: u16,
+    /// Seconds the client has to open the connection, otherwise the socket is closed.
+    pub(crate) handshake_timeout: u64,
+    /// Bytes of the largest message body the clients can publish, zero means no limit.
+    pub(crate) max_message_size: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            network: Network::default(),
+            limits: Limits::default(),
+            auth: Auth::default(),
+            vhosts: vec![VirtualHost { name: "/".into() }],
+        }
+    }
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Network {
+            listen: "127.0.0.1:5672".into(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            channel_max: 2047,
+            frame_max: 131_072,
+            heartbeat: 60,
+            handshake_timeout: 10,
+            max_message_size: 134_217_728,
+        }
+    }
+}
+
+/// Read the config file.
+pub(crate) fn load(path: &str) -> Result<Config> {
+    let text = fs::read_to_string(path)?;
+
+    parse(&text)
+}
+
+fn parse(text: &str) -> Result<Config> {
+    let config = toml::from_str(text)?;
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_are_defaults() {
+        let config = parse("[limits]\nchannel_max = 16\n").unwrap();
+
+        assert_eq!(config.network.listen, "127.0.0.1:5672");
+        assert_eq!(config.auth.users_file, None);
+        assert_eq!(
+            config.limits,
+            Limits {
+                channel_max: 16,
+                ..Limits::default()
+            }
+        );
+    }
+
+    #[test]
+    fn vhosts_replace_the_default() {
+        assert_eq!(parse("").unwrap().vhosts, vec![VirtualHost { name: "/".into() }]);
+
+        let config = parse("[[vhosts]]\nname = \"team\"\n").unwrap();
+
+        assert_eq!(config.vhosts, vec![VirtualHost { name: "team".into() }]);
+    }
+
+    #[test]
+    fn unknown_value_type_is_error() {
+        assert!(parse("[limits]\nframe_max = \"big\"\n").is_err());
+    }
+}
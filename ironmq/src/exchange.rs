@@ -20,7 +20,7 @@ pub(crate) struct Exchange {
 impl From<ExchangeDeclareArgs> for Exchange {
     fn from(f: ExchangeDeclareArgs) -> Self {
         Exchange {
-            name: f.exchange_name,
+            name: f.exchange,
             exchange_type: f.exchange_type,
             durable: ExchangeDeclareFlags::contains(&f.flags, ExchangeDeclareFlags::DURABLE),
             auto_delete: ExchangeDeclareFlags::contains(&f.flags, ExchangeDeclareFlags::AUTO_DELETE),
@@ -1,14 +1,16 @@
 use crate::Result;
-use crate::client::{error, state};
+use crate::client::error;
 use crate::exchange::Exchange;
 use crate::exchange::handler::{self, ExchangeCommand, ExchangeCommandSink};
 use crate::queue::handler::QueueCommandSink;
-use ironmq_codec::frame;
+use ironmq_codec::frame::{self, ReplyCode};
 use log::{debug, error};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
 
+/// Clones share the same exchanges.
+#[derive(Clone)]
 pub(crate) struct ExchangeManager {
     exchanges : Arc<Mutex<HashMap<String, ExchangeState>>>
 }
@@ -37,7 +39,7 @@ impl ExchangeManager {
         match ex.get(&exchange.name) {
             None =>
                 if passive {
-                    return error(0, frame::EXCHANGE_DECLARE, 404, "Exchange not found")
+                    return error(0, frame::EXCHANGE_DECLARE, ReplyCode::NotFound, "Exchange not found")
                 },
             Some(current) => {
                 debug!("Current instance {:?}", current.exchange);
@@ -45,7 +47,7 @@ impl ExchangeManager {
                 if current.exchange != exchange {
                     error!("Current exchange: {:?} to be declared. {:?}", current.exchange, exchange);
 
-                    return error(0, frame::EXCHANGE_DECLARE, state::PRECONDITION_FAILED,
+                    return error(0, frame::EXCHANGE_DECLARE, ReplyCode::PreconditionFailed,
                                  "Exchange exists but properties are different")
                 }
             }
@@ -80,7 +82,7 @@ impl ExchangeManager {
                 Ok(())
             },
             None =>
-                error(0, frame::QUEUE_BIND, 404, "Not found")
+                error(0, frame::QUEUE_BIND, ReplyCode::NotFound, "Not found")
         }
     }
 
@@ -100,14 +102,14 @@ mod tests {
         let mut exchanges = start();
         let mut args = ExchangeDeclareArgs::default();
 
-        args.exchange_name = "new exchange".to_string();
+        args.exchange = "new exchange".to_string();
         args.flags |= ExchangeDeclareFlags::PASSIVE;
 
         let result = exchanges.declare(args.into(), true, "").await;
 
         assert!(result.is_err());
         let err = result.unwrap_err().downcast::<RuntimeError>().unwrap();
-        assert_eq!(err.code, 404);
+        assert_eq!(err.code, ReplyCode::NotFound);
     }
 
     #[tokio::test]
@@ -118,13 +120,13 @@ mod tests {
         let mut exchanges = start();
 
         let mut args = ExchangeDeclareArgs::default();
-        args.exchange_name = exchange_name.clone();
+        args.exchange = exchange_name.clone();
         args.exchange_type = exchange_type.clone();
 
         let _ = exchanges.declare(args.into(), false, "").await;
 
         let mut args2 = ExchangeDeclareArgs::default();
-        args2.exchange_name = exchange_name.clone();
+        args2.exchange = exchange_name.clone();
         args2.exchange_type = "topic".to_string();
 
         let result = exchanges.declare(args2.into(), false, "").await;
@@ -133,7 +135,7 @@ mod tests {
 
         let err = result.unwrap_err().downcast::<RuntimeError>().unwrap();
         assert_eq!(err.scope, ErrorScope::Channel);
-        assert_eq!(err.code, state::PRECONDITION_FAILED);
+        assert_eq!(err.code, ReplyCode::PreconditionFailed);
     }
 
     #[tokio::test]
@@ -142,7 +144,7 @@ mod tests {
         let exchange_name = "orders".to_string();
 
         let mut args = ExchangeDeclareArgs::default();
-        args.exchange_name = exchange_name.clone();
+        args.exchange = exchange_name.clone();
         args.flags |= ExchangeDeclareFlags::DURABLE;
         args.flags |= ExchangeDeclareFlags::AUTO_DELETE;
 
@@ -1,7 +1,10 @@
+mod auth;
 mod client;
+mod config;
 mod exchange;
 mod message;
 mod queue;
+mod vhost;
 
 use env_logger::Builder;
 use log::{error, info};
@@ -9,6 +12,7 @@ use std::fmt;
 use std::io::Write;
 use std::sync::Arc;
 use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::Mutex;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -16,8 +20,8 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 
 pub(crate) struct Context {
-    pub(crate) exchanges: exchange::manager::ExchangeManager,
-    pub(crate) queues: queue::manager::QueueManager,
+    pub(crate) users: auth::UserStore,
+    pub(crate) vhosts: vhost::VirtualHostManager,
 }
 
 #[derive(Debug, PartialEq)]
@@ -32,11 +36,11 @@ impl Default for ErrorScope {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub(crate) struct RuntimeError {
     pub(crate) scope: ErrorScope,
     pub(crate) channel: ironmq_codec::frame::Channel,
-    pub(crate) code: u16,
+    pub(crate) code: ironmq_codec::frame::ReplyCode,
     pub(crate) text: String,
     pub(crate) class_id: u16,
     pub(crate) method_id: u16,
@@ -47,14 +51,14 @@ impl From<RuntimeError> for ironmq_codec::frame::AMQPFrame {
         match err.scope {
             ErrorScope::Connection => ironmq_codec::frame::connection_close(
                 err.channel,
-                err.code,
+                err.code.into(),
                 &err.text,
                 err.class_id,
                 err.method_id,
             ),
             ErrorScope::Channel => ironmq_codec::frame::channel_close(
                 err.channel,
-                err.code,
+                err.code.into(),
                 &err.text,
                 err.class_id,
                 err.method_id,
@@ -84,28 +88,70 @@ fn setup_logger() {
         .init();
 }
 
+/// Read the config file again on `SIGHUP`, and create or delete the virtual hosts according to it.
+fn reload_on_hangup(path: String, context: Arc<Mutex<Context>>) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if let Err(e) = reload(&path, &context).await {
+                error!("Cannot reload config {}: {}", path, e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Sync the virtual hosts with the config file. The connections of the deleted virtual hosts are
+/// closed by the server.
+async fn reload(path: &str, context: &Mutex<Context>) -> Result<()> {
+    let config = config::load(path)?;
+    let mut ctx = context.lock().await;
+
+    ctx.vhosts.sync(config.vhosts.iter().map(|v| v.name.as_str()));
+
+    Ok(())
+}
+
 #[tokio::main]
 pub async fn main() -> Result<()> {
     setup_logger();
 
-    let exchanges = exchange::manager::start();
-    let queues = queue::manager::start();
+    // the optional argument is the path of the config file
+    let config_path = std::env::args().nth(1);
+    let config = match &config_path {
+        Some(path) => config::load(path)?,
+        None => config::Config::default(),
+    };
+
+    let users = match &config.auth.users_file {
+        Some(path) => auth::UserStore::load(path)?,
+        None => auth::UserStore::default(),
+    };
+
+    let vhosts = vhost::start(config.vhosts.iter().map(|v| v.name.as_str()));
 
     let context = Arc::new(Mutex::new(Context {
-        exchanges: exchanges,
-        queues: queues
+        users,
+        vhosts,
     }));
 
-    info!("Listening on port 5672");
+    if let Some(path) = config_path {
+        reload_on_hangup(path, context.clone())?;
+    }
+
+    info!("Listening on {}", config.network.listen);
 
-    let listener = TcpListener::bind("127.0.0.1:5672").await?;
+    let listener = TcpListener::bind(&config.network.listen).await?;
 
     loop {
         let (socket, _) = listener.accept().await?;
         let ctx = context.clone();
+        let limits = config.limits;
 
         tokio::spawn(async move {
-            if let Err(e) = client::conn::handle_client(socket, ctx).await {
+            if let Err(e) = client::conn::handle_client(socket, ctx, limits).await {
                 error!("Error handling client {:?}", e)
             }
 
@@ -1,5 +1,7 @@
 //! Messages are sent to exhchanges and forwarded to queues. There is a
 //! possibility to state that a message is processed via an oneshot channel.
+use bytes::Bytes;
+use ironmq_codec::frame::BasicProperties;
 use tokio::sync::{mpsc};
 
 //pub(crate) type MessageId = String;
@@ -8,7 +10,9 @@ use tokio::sync::{mpsc};
 pub(crate) struct Message {
     /// Id of the connection sent this message.
     pub(crate) source_connection: String,
-    pub(crate) content: Vec<u8>,
+    /// Properties of the content header, they are delivered to the consumers as they are.
+    pub(crate) properties: BasicProperties,
+    pub(crate) content: Bytes,
 }
 
 pub(crate) type MessageChannel = mpsc::Sender<Message>;
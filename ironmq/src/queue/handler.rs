@@ -5,7 +5,8 @@ use std::collections::HashMap;
 use tokio::sync::{mpsc, oneshot};
 
 pub(crate) type QueueCommandSink = mpsc::Sender<QueueCommand>;
-pub(crate) type FrameSink = mpsc::Sender<frame::AMQPFrame>;
+/// Frames of a delivery are sent together, so they can be written to the socket at once.
+pub(crate) type FrameSink = mpsc::Sender<Vec<frame::AMQPFrame>>;
 //pub(crate) type FrameStream = mpsc::Receiver<frame::AMQPFrame>;
 
 #[derive(Debug)]
@@ -23,18 +24,20 @@ pub(crate) async fn queue_loop(commands: &mut mpsc::Receiver<QueueCommand>) {
             QueueCommand::Message(message) => {
                 let frames = vec![
                     frame::basic_deliver(1, "ctag".into(), 0, false, "exchange".into(), "rkey".into()),
-                    frame::AMQPFrame::ContentHeader(frame::content_header(1, message.content.len() as u64)),
-                    frame::AMQPFrame::ContentBody(frame::content_body(1, message.content.as_slice())),
+                    frame::AMQPFrame::ContentHeader(frame::content_header_with_properties(
+                        1,
+                        message.content.len() as u64,
+                        message.properties.clone(),
+                    )),
+                    frame::AMQPFrame::ContentBody(frame::content_body_bytes(1, message.content.clone())),
                 ];
 
-                'consumer: for (_, consumer) in &consumers {
-                    for f in &frames {
-                        debug!("Sending frame {:?}", f);
+                for (_, consumer) in &consumers {
+                    debug!("Sending frames {:?}", frames);
 
-                        if let Err(e) = consumer.send(f.clone()).await {
-                            error!("Message send error {:?}", e);
-                            break 'consumer;
-                        }
+                    if let Err(e) = consumer.send(frames.clone()).await {
+                        error!("Message send error {:?}", e);
+                        break;
                     }
                 }
             },
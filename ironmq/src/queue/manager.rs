@@ -3,10 +3,12 @@ use crate::client::error;
 use crate::queue::Queue;
 use crate::queue::handler::{self, QueueCommand, QueueCommandSink};
 use std::collections::HashMap;
-use ironmq_codec::frame::{self, AMQPFrame};
+use ironmq_codec::frame::{self, AMQPFrame, ReplyCode};
 use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot, Mutex};
 
+/// Clones share the same queues.
+#[derive(Clone)]
 pub(crate) struct QueueManager {
     queues: Arc<Mutex<HashMap<String, Queue>>>
 }
@@ -54,11 +56,11 @@ impl QueueManager {
                 Ok(queue.command_sink.clone()),
             None =>
                 // TODO check error code because we can call this from several places
-                error(0, frame::QUEUE_DECLARE, 404, "Not found")
+                error(0, frame::QUEUE_DECLARE, ReplyCode::NotFound, "Not found")
         }
     }
 
-    pub(crate) async fn consume(&mut self, name: String, consumer_tag: String, outgoing: mpsc::Sender<AMQPFrame>) -> Result<()> {
+    pub(crate) async fn consume(&mut self, name: String, consumer_tag: String, outgoing: mpsc::Sender<Vec<AMQPFrame>>) -> Result<()> {
         let q = self.queues.lock().await;
 
         match q.get(&name) {
@@ -75,7 +77,7 @@ impl QueueManager {
                 Ok(())
             },
             None =>
-                error(0, frame::BASIC_CONSUME, 404, "Not found")
+                error(0, frame::BASIC_CONSUME, ReplyCode::NotFound, "Not found")
         }
     }
 
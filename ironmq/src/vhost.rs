@@ -0,0 +1,143 @@
+//! Virtual hosts separate the exchanges and queues of the clients, every virtual host has its own
+//! exchange and queue manager. A connection is bound to the virtual host it opened, and it is
+//! closed when the virtual host is deleted.
+use crate::exchange::{self, manager::ExchangeManager};
+use crate::queue::{self, manager::QueueManager};
+use log::info;
+use std::collections::HashMap;
+use tokio::sync::watch;
+
+/// Handle of a virtual host, the connections keep a clone of it.
+#[derive(Clone)]
+pub(crate) struct VirtualHost {
+    pub(crate) name: String,
+    pub(crate) exchanges: ExchangeManager,
+    pub(crate) queues: QueueManager,
+    /// Changes to true when the virtual host is deleted.
+    deleted: watch::Receiver<bool>,
+}
+
+struct VirtualHostState {
+    vhost: VirtualHost,
+    delete: watch::Sender<bool>,
+}
+
+pub(crate) struct VirtualHostManager {
+    vhosts: HashMap<String, VirtualHostState>,
+}
+
+pub(crate) fn start<'a, I: IntoIterator<Item = &'a str>>(names: I) -> VirtualHostManager {
+    let mut manager = VirtualHostManager { vhosts: HashMap::new() };
+
+    for name in names {
+        manager.create(name);
+    }
+
+    manager
+}
+
+impl VirtualHost {
+    /// Receiver which is notified when the virtual host is deleted.
+    pub(crate) fn deleted(&self) -> watch::Receiver<bool> {
+        self.deleted.clone()
+    }
+}
+
+impl VirtualHostManager {
+    pub(crate) fn get(&self, name: &str) -> Option<VirtualHost> {
+        self.vhosts.get(name).map(|state| state.vhost.clone())
+    }
+
+    /// Create a virtual host with empty exchanges and queues. It returns false if the virtual
+    /// host already exists.
+    pub(crate) fn create(&mut self, name: &str) -> bool {
+        if self.vhosts.contains_key(name) {
+            return false;
+        }
+
+        let (delete, deleted) = watch::channel(false);
+        let vhost = VirtualHost {
+            name: name.to_string(),
+            exchanges: exchange::manager::start(),
+            queues: queue::manager::start(),
+            deleted,
+        };
+
+        info!("Virtual host {} is created", name);
+
+        self.vhosts.insert(name.to_string(), VirtualHostState { vhost, delete });
+
+        true
+    }
+
+    /// Delete the virtual host and notify its connections. It returns false if the virtual host
+    /// doesn't exist.
+    pub(crate) fn delete(&mut self, name: &str) -> bool {
+        match self.vhosts.remove(name) {
+            Some(state) => {
+                info!("Virtual host {} is deleted", name);
+
+                let _ = state.delete.send(true);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Create and delete virtual hosts, so only the given ones remain.
+    pub(crate) fn sync<'a, I: IntoIterator<Item = &'a str>>(&mut self, names: I) {
+        let names: Vec<&str> = names.into_iter().collect();
+        let removed: Vec<String> = self
+            .vhosts
+            .keys()
+            .filter(|name| !names.contains(&name.as_str()))
+            .cloned()
+            .collect();
+
+        for name in removed {
+            self.delete(&name);
+        }
+
+        for name in names {
+            self.create(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironmq_codec::frame::ExchangeDeclareArgs;
+
+    #[tokio::test]
+    async fn virtual_hosts_have_separate_exchanges() {
+        let vhosts = start(vec!["/", "team"]);
+        let args = || ExchangeDeclareArgs {
+            exchange: "orders".into(),
+            ..ExchangeDeclareArgs::default()
+        };
+
+        let mut root = vhosts.get("/").unwrap();
+        root.exchanges.declare(args().into(), false, "").await.unwrap();
+
+        let mut team = vhosts.get("team").unwrap();
+
+        assert!(team.exchanges.declare(args().into(), true, "").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn deleted_virtual_host_notifies_connections() {
+        let mut vhosts = start(vec!["/"]);
+        let mut deleted = vhosts.get("/").unwrap().deleted();
+
+        vhosts.sync(vec!["team"]);
+
+        deleted.changed().await.unwrap();
+
+        assert!(*deleted.borrow());
+        assert!(vhosts.get("/").is_none());
+        assert!(vhosts.get("team").is_some());
+        assert!(!vhosts.delete("/"));
+    }
+}
@@ -28,7 +28,304 @@ async fn open_already_opened_channel() -> client::Result<()> {
 
     let err = ironmq_test::to_client_error(result);
 
-    assert_eq!(err.code, 504);
+    assert_eq!(err.code, client::ReplyCode::ChannelError);
 
     Ok(())
 }
+
+#[cfg(feature = "integration-tests")]
+#[test]
+fn unsupported_protocol_version_is_answered_with_server_header() {
+    use std::io::{Read, Write};
+
+    let mut socket = std::net::TcpStream::connect("127.0.0.1:5672").unwrap();
+    socket.write_all(b"AMQP\x01\x01\x00\x0A").unwrap();
+
+    let mut header = [0u8; 8];
+    socket.read_exact(&mut header).unwrap();
+
+    assert_eq!(&header, ironmq_codec::codec::PROTOCOL_HEADER);
+
+    // server closes the connection
+    assert_eq!(socket.read(&mut header).unwrap(), 0);
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn update_secret() -> client::Result<()> {
+    let c = client::connect("127.0.0.1:5672").await?;
+    c.open("/").await?;
+
+    c.update_secret("guest", "token refresh").await?;
+
+    c.close().await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn update_secret_with_wrong_secret_is_access_refused() -> client::Result<()> {
+    let c = client::connect("127.0.0.1:5672").await?;
+    c.open("/").await?;
+
+    let result = c.update_secret("new-secret", "token refresh").await;
+
+    assert!(result.is_err());
+
+    let err = ironmq_test::to_client_error(result);
+
+    assert_eq!(err.code, client::ReplyCode::AccessRefused);
+
+    Ok(())
+}
+
+#[cfg(feature = "integration-tests")]
+type RawConnection = tokio_util::codec::Framed<tokio::net::TcpStream, ironmq_codec::codec::AMQPCodec>;
+
+/// Open a connection with frames, the client answers the tune with the given limits.
+#[cfg(feature = "integration-tests")]
+async fn raw_connection(channel_max: u16, frame_max: u32, heartbeat: u16) -> client::Result<RawConnection> {
+    use futures::{SinkExt, StreamExt};
+    use ironmq_codec::frame;
+
+    let mut framed = tune(channel_max, frame_max, heartbeat).await?;
+
+    framed.send(frame::connection_open(0, "/")).await?;
+    framed.next().await.unwrap()?;
+
+    Ok(framed)
+}
+
+/// Log in and send the tune-ok with the given limits.
+#[cfg(feature = "integration-tests")]
+async fn tune(channel_max: u16, frame_max: u32, heartbeat: u16) -> client::Result<RawConnection> {
+    use futures::{SinkExt, StreamExt};
+    use ironmq_codec::codec::{AMQPCodec, DEFAULT_FRAME_MAX};
+    use ironmq_codec::frame::{self, AMQPFrame, MethodFrameArgs};
+
+    let socket = tokio::net::TcpStream::connect("127.0.0.1:5672").await?;
+    let mut framed = tokio_util::codec::Framed::new(socket, AMQPCodec::new(DEFAULT_FRAME_MAX));
+
+    framed.send(AMQPFrame::Header).await?;
+    framed.next().await.unwrap()?;
+    framed.send(frame::connection_start_ok("guest", "guest", frame::FieldTable::new())).await?;
+
+    let tune = match framed.next().await.unwrap()? {
+        AMQPFrame::Method(_, _, MethodFrameArgs::ConnectionTune(args)) => args,
+        f => panic!("Tune expected, got {:?}", f),
+    };
+
+    assert!(tune.channel_max == 0 || tune.channel_max > channel_max);
+    assert!(tune.frame_max == 0 || tune.frame_max > frame_max);
+
+    let tune_ok = MethodFrameArgs::ConnectionTuneOk(frame::ConnectionTuneOkArgs {
+        channel_max,
+        frame_max,
+        heartbeat,
+    });
+
+    framed.send(AMQPFrame::Method(0, frame::CONNECTION_TUNE_OK, tune_ok)).await?;
+
+    Ok(framed)
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn frame_max_below_minimum_closes_connection() -> client::Result<()> {
+    let mut framed = tune(0, 1, 0).await?;
+
+    expect_connection_close(&mut framed, client::ReplyCode::NotAllowed).await
+}
+
+#[cfg(feature = "integration-tests")]
+async fn expect_connection_close(framed: &mut RawConnection, code: client::ReplyCode) -> client::Result<()> {
+    use futures::StreamExt;
+    use ironmq_codec::frame::{self, AMQPFrame, MethodFrameArgs};
+
+    match framed.next().await.unwrap()? {
+        AMQPFrame::Method(0, frame::CONNECTION_CLOSE, MethodFrameArgs::ConnectionClose(args)) => {
+            assert_eq!(args.reply_code, u16::from(code));
+        }
+        f => panic!("Connection close expected, got {:?}", f),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn channel_above_negotiated_channel_max_closes_connection() -> client::Result<()> {
+    use futures::{SinkExt, StreamExt};
+    use ironmq_codec::frame;
+
+    let mut framed = raw_connection(10, 4096, 0).await?;
+
+    framed.send(frame::channel_open(10)).await?;
+    framed.next().await.unwrap()?;
+
+    framed.send(frame::channel_open(11)).await?;
+
+    expect_connection_close(&mut framed, client::ReplyCode::NotAllowed).await
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn frame_above_negotiated_frame_max_closes_connection() -> client::Result<()> {
+    use futures::{SinkExt, StreamExt};
+    use ironmq_codec::frame::{self, content_header, AMQPFrame, ContentBodyFrame};
+
+    let mut framed = raw_connection(10, 4096, 0).await?;
+
+    framed.send(frame::channel_open(1)).await?;
+    framed.next().await.unwrap()?;
+
+    let body = vec![b'x'; 5000];
+    let frames = vec![
+        frame::basic_publish(1, "", "key"),
+        AMQPFrame::ContentHeader(content_header(1, body.len() as u64)),
+        AMQPFrame::ContentBody(ContentBodyFrame { channel: 1, body: body.into() }),
+    ];
+
+    framed.send(frames).await?;
+
+    expect_connection_close(&mut framed, client::ReplyCode::FrameError).await
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn silent_client_gets_heartbeats_then_disconnected() -> client::Result<()> {
+    use futures::StreamExt;
+    use ironmq_codec::frame::AMQPFrame;
+    use std::time::Duration;
+
+    let mut framed = raw_connection(10, 4096, 1).await?;
+
+    let hb = tokio::time::timeout(Duration::from_secs(2), framed.next()).await?;
+
+    assert!(matches!(hb, Some(Ok(AMQPFrame::Heartbeat(0)))));
+
+    // we don't send anything, after two intervals the server closes the socket
+    loop {
+        match tokio::time::timeout(Duration::from_secs(5), framed.next()).await? {
+            Some(Ok(AMQPFrame::Heartbeat(0))) => continue,
+            None => break,
+            f => panic!("Heartbeat or close expected, got {:?}", f),
+        }
+    }
+
+    Ok(())
+}
+
+/// Send the header and a start-ok with the credentials.
+#[cfg(feature = "integration-tests")]
+async fn login(username: &str, password: &str, failure_close: bool) -> client::Result<RawConnection> {
+    use futures::{SinkExt, StreamExt};
+    use ironmq_codec::codec::{AMQPCodec, DEFAULT_FRAME_MAX};
+    use ironmq_codec::frame::{self, AMQPFieldValue, AMQPFrame};
+
+    let socket = tokio::net::TcpStream::connect("127.0.0.1:5672").await?;
+    let mut framed = tokio_util::codec::Framed::new(socket, AMQPCodec::new(DEFAULT_FRAME_MAX));
+
+    framed.send(AMQPFrame::Header).await?;
+    framed.next().await.unwrap()?;
+
+    let mut caps = frame::FieldTable::new();
+    caps.insert("authentication_failure_close".into(), AMQPFieldValue::Bool(failure_close));
+
+    framed.send(frame::connection_start_ok(username, password, caps)).await?;
+
+    Ok(framed)
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn wrong_password_is_access_refused() -> client::Result<()> {
+    let mut framed = login("guest", "wrong", true).await?;
+
+    expect_connection_close(&mut framed, client::ReplyCode::AccessRefused).await
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn non_utf8_password_is_access_refused() -> client::Result<()> {
+    use futures::{SinkExt, StreamExt};
+    use ironmq_codec::codec::{AMQPCodec, DEFAULT_FRAME_MAX};
+    use ironmq_codec::frame::{self, AMQPFieldValue, AMQPFrame, MethodFrameArgs};
+
+    let socket = tokio::net::TcpStream::connect("127.0.0.1:5672").await?;
+    let mut framed = tokio_util::codec::Framed::new(socket, AMQPCodec::new(DEFAULT_FRAME_MAX));
+
+    framed.send(AMQPFrame::Header).await?;
+    framed.next().await.unwrap()?;
+
+    let mut caps = frame::FieldTable::new();
+    caps.insert("authentication_failure_close".into(), AMQPFieldValue::Bool(true));
+
+    let mut start_ok = frame::connection_start_ok("guest", "guest", caps);
+
+    if let AMQPFrame::Method(_, _, MethodFrameArgs::ConnectionStartOk(args)) = &mut start_ok {
+        args.response = b"\x00guest\x00\xFF\xFE"[..].into();
+    }
+
+    framed.send(start_ok).await?;
+
+    expect_connection_close(&mut framed, client::ReplyCode::AccessRefused).await
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn wrong_password_without_failure_close_disconnects() -> client::Result<()> {
+    use futures::StreamExt;
+
+    let mut framed = login("nobody", "guest", false).await?;
+
+    assert!(framed.next().await.is_none());
+
+    Ok(())
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn open_not_existing_vhost_is_not_allowed() -> client::Result<()> {
+    let c = client::connect("127.0.0.1:5672").await?;
+
+    let result = c.open("/not-existing").await;
+
+    assert!(result.is_err());
+
+    let err = ironmq_test::to_client_error(result);
+
+    assert_eq!(err.code, client::ReplyCode::NotAllowed);
+
+    Ok(())
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn method_before_connection_open_is_command_invalid() -> client::Result<()> {
+    use futures::{SinkExt, StreamExt};
+    use ironmq_codec::frame;
+
+    let mut framed = login("guest", "guest", true).await?;
+
+    // tune
+    framed.next().await.unwrap()?;
+
+    framed.send(frame::queue_declare(1, "queue")).await?;
+
+    expect_connection_close(&mut framed, client::ReplyCode::CommandInvalid).await
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn method_on_not_opened_channel_is_channel_error() -> client::Result<()> {
+    use futures::SinkExt;
+    use ironmq_codec::frame;
+
+    let mut framed = raw_connection(10, 4096, 0).await?;
+
+    framed.send(frame::queue_declare(3, "queue")).await?;
+
+    expect_connection_close(&mut framed, client::ReplyCode::ChannelError).await
+}
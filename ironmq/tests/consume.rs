@@ -28,3 +28,26 @@ async fn consume() -> client::Result<()> {
 
     Ok(())
 }
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn consume_message_bigger_than_frame_max() -> client::Result<()> {
+    let exchange = "big-messages";
+    let queue = "queue-big";
+    let c = default_connection(exchange, queue).await?;
+
+    let (otx, orx) = oneshot::channel();
+    helper::conn::consume_messages(&c, 1, queue, "ctag", otx, 1).await?;
+
+    let payload = "x".repeat(300_000);
+    c.basic_publish(1, exchange, "", payload.clone()).await?;
+
+    let msgs = orx.await.unwrap();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].body, payload.into_bytes());
+
+    c.channel_close(1).await?;
+    c.close().await?;
+
+    Ok(())
+}
@@ -58,13 +58,45 @@ async fn channel_close_on_not_existing_exchange() {
             assert!(r.is_err());
 
             let err = ironmq_test::to_client_error(r);
-            assert_eq!(err.code, 404);
+            assert_eq!(err.code, client::ReplyCode::NotFound);
 
             Ok(())
         }))
         .check().await;
 }
 
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn channel_can_be_reopened_after_channel_error() {
+    Steps
+        ::feature("Channel can be reopened after the server closed it", init!(World, {
+            Ok(World {
+                conn: client::connect("127.0.0.1:5672").await?,
+                result: Ok(())
+            })
+        })).await
+        .given("a channel closed by a channel error", step!(|w: World| {
+            w.conn.open("/").await?;
+            w.conn.channel_open(1).await?;
+
+            let mut flags = ironmq_codec::frame::ExchangeDeclareFlags::empty();
+            flags |= ironmq_codec::frame::ExchangeDeclareFlags::PASSIVE;
+
+            let result = w.conn.exchange_declare(1, "sure does not exist", "fanout", Some(flags)).await;
+            assert_eq!(ironmq_test::to_client_error(result).code, client::ReplyCode::NotFound);
+
+            Ok(())
+        }))
+        .when("the same channel is opened again", step!(|w: World| {
+            w.conn.channel_open(1).await
+        }))
+        .then("it can be used", step!(|w: World| {
+            let flags = ironmq_codec::frame::ExchangeDeclareFlags::empty();
+            w.conn.exchange_declare(1, "reopened channel", "fanout", Some(flags)).await
+        }))
+        .check().await;
+}
+
 #[cfg(feature = "integration-tests")]
 #[tokio::test]
 async fn passive_exchange_declare_check_if_exchange_exist() {
@@ -76,6 +108,9 @@ async fn passive_exchange_declare_check_if_exchange_exist() {
             })
         })).await
         .given("an exchange declared", step!(|w: World| {
+            w.conn.open("/").await?;
+            w.conn.channel_open(1).await?;
+
             let mut flags = ironmq_codec::frame::ExchangeDeclareFlags::empty();
             w.conn.exchange_declare(1, "new channel", "fanout", Some(flags)).await
         }))
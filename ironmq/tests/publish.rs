@@ -91,7 +91,7 @@ async fn publish_to_intenal_exchange() {
             assert!(res.is_err());
 
             let err = ironmq_test::to_client_error(res);
-            assert_eq!(err.code, 403);
+            assert_eq!(err.code, client::ReplyCode::AccessRefused);
 
             Ok(())
         }))